@@ -1,26 +1,30 @@
 #![feature(int_roundings)]
 use descent::{module::*, prelude::*, optimizer::*, module::ModuleExt};
 use image::{Rgb32FImage, DynamicImage, GenericImageView};
-use rand::{thread_rng, RngCore, Rng};
+use rand::{RngCore, Rng};
 use std::{io::Write, collections::HashMap};
-
-mod fcn;
-use crate::fcn::FCN;
+use descent_unet_example::{config::ExperimentConfig, seed::RunSeed, fcn::FCN, train};
 
 fn main() {
-    let mut rng = thread_rng();
     let mut env = Environment::new();
 
-    let fcn = FCN::new(&mut env, 1, 3, 2, 16, 3);
+    let config = ExperimentConfig::load("examples/colorizing_fcn/config.toml").unwrap_or_else(|e| panic!("{e}"));
+    let model = &config.model;
+    let seed = RunSeed::from_config(config.seed);
+    eprintln!("Seed {}", seed.0);
+    let mut init_rng = seed.init_rng();
+    let mut graph_rng = seed.graph_rng();
+
+    let fcn = FCN::new(&mut env, model.inputs, model.outputs, model.depth, model.width, model.kernelsize);
     
-    let image = image::open("images/Capybara_128px_square.jpg").expect("Could not open image");
+    let image = image::open(&config.dataset.images[0]).expect("Could not open image");
 
 
 
     let image_bytes_bw = image.to_luma32f().to_vec();
 
     let input_param = env.static_parameter_with_data(
-        [1, 128, 128, 1],
+        [1, image.height() as usize, image.width() as usize, 1],
          "input", 
          &image_bytes_bw
     );
@@ -62,12 +66,7 @@ fn main() {
     let (train_graph, parameters, optimizer) = {
         let scope = env.scope();
         let x = fcn.train(scope.parameter(&input_param));
-        //this is just mse loss
-        let loss = (x - &target_param).square()
-            .reduce_sum(-1, false)
-            .reduce_sum(-1, false)
-            .reduce_sum(-1, false)
-            .set_loss(); 
+        let loss = train::loss(&config.loss, x, &target_param).set_loss();
         scope.update_parameter_value(&loss_param, |loss_sum| {
             loss_sum + loss.reduce_sum(0, false)
         });
@@ -77,16 +76,16 @@ fn main() {
             &mut env,
             &scope,
             &parameters,
-            0.04,
-            0.99,
-            0.999,
-            1.0E-8
+            config.optimizer.learning_rate,
+            config.optimizer.beta1,
+            config.optimizer.beta2,
+            config.optimizer.epsilon
         );
         (scope.build_graph(), parameters, optimizer)
     };
 
     for param in parameters.iter(){
-        env.reset_parameter(param, &mut rng);
+        env.reset_parameter(param, &mut init_rng);
     }
 
     let inference_graph = env.build_graph(|scope| {
@@ -97,16 +96,16 @@ fn main() {
 
     let mut epoch = 1;
     eprintln!("Starting training");
-    for _e in 0 .. 240{
-        //Train for 10 steps
-        for batch in 0 .. 10{
+    for _e in 0 .. config.epochs{
+        //Train for a few steps
+        for batch in 0 .. config.schedule.steps_per_epoch{
             env.writer(&loss_param).zero_fill();
-            env.run(&train_graph, rng.next_u32());
+            env.run(&train_graph, graph_rng.next_u32());
             let loss = env.read_parameter_scalar(&loss_param) / output_shape_vec.iter().product::<usize>() as f32;
             eprint!("\rEpoch {epoch} Batch {batch} Loss={}      ", loss);
         }
         //Evaluate
-        env.run(&inference_graph, rng.next_u32());
+        env.run(&inference_graph, graph_rng.next_u32());
 
         let output_values = env.read_parameter_to_vec(&output_param);
         let scaled_image = Rgb32FImage::from_vec(output_shape[2] as u32, output_shape[1] as u32, output_values).unwrap();
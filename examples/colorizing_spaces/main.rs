@@ -0,0 +1,43 @@
+use descent::prelude::*;
+use descent_unet_example::{checkpoint::Checkpoint, color::{self, ColorSpace}, config::ExperimentConfig, data, network::Network, seed::RunSeed, tiling::TiledInference, train};
+use std::path::Path;
+
+//Trains the same network on the same data once per colour space and compares the colorized images in rgb
+fn main() {
+    let base = ExperimentConfig::load("examples/colorizing_spaces/config.toml").unwrap_or_else(|e| panic!("{e}"));
+    let (rgb, height, width) = data::load_image(&base.dataset.images[0], 3).expect("Could not open image");
+    let tile = base.dataset.tile_size;
+
+    let mut results = Vec::new();
+    for space in [ColorSpace::Rgb, ColorSpace::Lab, ColorSpace::YCbCr] {
+        let name = format!("{space:?}").to_lowercase();
+        let mut config = base.clone();
+        config.model.outputs = space.target_channels();
+        config.dataset.colorize = Some(space);
+        let output_dir = Path::new("runs/colorizing_spaces").join(&name);
+        train::train(&config, &output_dir);
+
+        //Colorize the full image from the final checkpoint and go back to rgb
+        let extension = config.schedule.checkpoint_format.extension();
+        let checkpoint = Checkpoint::load(output_dir.join(format!("checkpoint.{extension}"))).expect("Could not load checkpoint");
+        let mut env = Environment::new();
+        let network = Network::new(&mut env, &config.model);
+        let parameters = network.parameters(&mut env, [1, tile, tile, 1]);
+        checkpoint.upload(&mut env, &network.parameter_names(), &parameters).expect("Checkpoint does not match the network");
+        let tiled = TiledInference::new(&mut env, &network, 4, tile, tile, 1);
+        let (lightness, _) = space.split(&rgb);
+        let mut input = lightness.clone();
+        if let Some(normalization) = &checkpoint.config.model.normalization {
+            normalization.apply(&mut input);
+        }
+        let mut rng = RunSeed::from_config(checkpoint.config.seed).graph_rng();
+        let output = tiled.run(&mut env, &input, height, width, &mut rng);
+        let colorized = space.combine(&lightness, &output);
+        data::save_image(format!("capybara_colorized_{name}.png"), &colorized, height, width, 3).expect("Could not save image");
+        results.push((space, color::rgb_error(&colorized, &rgb)));
+    }
+
+    for (space, (mse, psnr)) in results {
+        println!("{space:?}: rgb mse {mse:.5}, psnr {psnr:.2} dB");
+    }
+}
@@ -1,11 +1,13 @@
 #![feature(int_roundings)]
 use descent::{module::*, prelude::*, optimizer::*, module::ModuleExt};
 use image::{Rgb32FImage, DynamicImage};
-use rand::{thread_rng, RngCore, Rng};
+use rand::{RngCore, Rng};
+use descent_unet_example::seed::RunSeed;
 use std::{io::Write, collections::HashMap};
 
 fn main() {
-    let mut rng = thread_rng();
+    //Nothing random happens in this graph, the seed only has to be fixed
+    let mut rng = RunSeed(0).graph_rng();
     let mut env = Environment::new();
     
     let image = image::open("images/Capybara_128px_square.jpg").expect("Could not open image");
@@ -0,0 +1,247 @@
+use crate::{seed::standard_normal, tiling::mirror_index};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+//An input and its target with the same height and width, both HWC
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImagePair {
+    pub input: Vec<f32>,
+    pub target: Vec<f32>,
+    pub height: usize,
+    pub width: usize,
+    pub input_channels: usize,
+    pub target_channels: usize,
+    //Label masks must not be blended, so they are resampled with nearest neighbour
+    pub target_is_label: bool,
+}
+
+//One step of the augmentation pipeline. Geometric steps move input and target together,
+//photometric steps (jitter, noise) only touch the input
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Augmentation {
+    //Each enabled flip happens with probability 0.5
+    Flip { horizontal: bool, vertical: bool },
+    //Rotation by a random multiple of 90 degrees
+    Rotate90,
+    //Zooms into a random window covering at least min_scale of each side
+    Crop { min_scale: f32 },
+    //Random rotation, scaling, shear and translation around the center, borders are mirrored
+    Affine { max_rotation: f32, max_scale: f32, max_shear: f32, max_translation: f32 },
+    //Brightness offset, contrast factor around the mean and hue rotation (fraction of a full turn, rgb inputs only)
+    ColorJitter { brightness: f32, contrast: f32, hue: f32 },
+    GaussianNoise { std: f32 },
+    //Smooth random displacement field as in the U-Net paper: uniform noise in [-1, 1]
+    //blurred by a gaussian with sigma pixels and scaled to alpha pixels
+    Elastic { alpha: f32, sigma: f32 },
+}
+
+//Applies every step in order
+pub fn apply(pipeline: &[Augmentation], pair: &mut ImagePair, rng: &mut impl Rng) {
+    for augmentation in pipeline.iter() {
+        augmentation.apply(pair, rng);
+    }
+}
+
+impl Augmentation {
+    pub fn validate(&self) -> Result<(), String> {
+        let ok = match *self {
+            Augmentation::Flip { .. } | Augmentation::Rotate90 => true,
+            Augmentation::Crop { min_scale } => min_scale > 0.0 && min_scale <= 1.0,
+            Augmentation::Affine { max_rotation, max_scale, max_shear, max_translation } =>
+                max_rotation >= 0.0 && max_scale >= 0.0 && (0.0 .. 90.0).contains(&max_shear) && max_translation >= 0.0,
+            Augmentation::ColorJitter { brightness, contrast, hue } =>
+                brightness >= 0.0 && (0.0 ..= 1.0).contains(&contrast) && (0.0 ..= 0.5).contains(&hue),
+            Augmentation::GaussianNoise { std } => std >= 0.0,
+            Augmentation::Elastic { alpha, sigma } => alpha >= 0.0 && sigma > 0.0,
+        };
+        if ok { Ok(()) } else { Err(format!("Augmentation parameters out of range: {self:?}")) }
+    }
+
+    pub fn apply(&self, pair: &mut ImagePair, rng: &mut impl Rng) {
+        match *self {
+            Augmentation::Flip { horizontal, vertical } => {
+                let flip_x = horizontal && rng.gen();
+                let flip_y = vertical && rng.gen();
+                if flip_x || flip_y {
+                    let (height, width) = (pair.height, pair.width);
+                    let (h, w) = (height as isize, width as isize);
+                    remap(pair, height, width, |y, x| {
+                        (if flip_y { h - 1 - y } else { y }, if flip_x { w - 1 - x } else { x })
+                    });
+                }
+            }
+            Augmentation::Rotate90 => {
+                let (height, width) = (pair.height, pair.width);
+                let (h, w) = (height as isize, width as isize);
+                match rng.gen_range(0 .. 4) {
+                    1 => remap(pair, width, height, |y, x| (x, w - 1 - y)),
+                    2 => remap(pair, height, width, |y, x| (h - 1 - y, w - 1 - x)),
+                    3 => remap(pair, width, height, |y, x| (h - 1 - x, y)),
+                    _ => {}
+                }
+            }
+            Augmentation::Crop { min_scale } => {
+                let scale_y = rng.gen_range(min_scale ..= 1.0);
+                let scale_x = rng.gen_range(min_scale ..= 1.0);
+                let (h, w) = (pair.height as f32, pair.width as f32);
+                let top = rng.gen_range(0.0 ..= h * (1.0 - scale_y));
+                let left = rng.gen_range(0.0 ..= w * (1.0 - scale_x));
+                warp(pair, |y, x| (top + y * scale_y, left + x * scale_x));
+            }
+            Augmentation::Affine { max_rotation, max_scale, max_shear, max_translation } => {
+                let rotation = rng.gen_range(-max_rotation ..= max_rotation).to_radians();
+                let shear = rng.gen_range(-max_shear ..= max_shear).to_radians();
+                let scale = rng.gen_range(1.0 / (1.0 + max_scale) ..= 1.0 + max_scale);
+                let (h, w) = (pair.height as f32, pair.width as f32);
+                let shift_y = rng.gen_range(-max_translation ..= max_translation) * h;
+                let shift_x = rng.gen_range(-max_translation ..= max_translation) * w;
+                let (cy, cx) = ((h - 1.0) / 2.0, (w - 1.0) / 2.0);
+                //Inverse mapping from output to source coordinates
+                let (sin, cos) = rotation.sin_cos();
+                let tan = shear.tan();
+                warp(pair, |y, x| {
+                    let (dy, dx) = (y - cy - shift_y, x - cx - shift_x);
+                    let (dy, dx) = ((cos * dy - sin * dx) / scale, (sin * dy + cos * dx) / scale);
+                    (cy + dy, cx + dx + tan * dy)
+                });
+            }
+            Augmentation::ColorJitter { brightness, contrast, hue } => {
+                let offset = rng.gen_range(-brightness ..= brightness);
+                let factor = rng.gen_range(1.0 - contrast ..= 1.0 + contrast);
+                let angle = rng.gen_range(-hue ..= hue) * std::f32::consts::TAU;
+                let mean = pair.input.iter().sum::<f32>() / pair.input.len() as f32;
+                if pair.input_channels == 3 && hue > 0.0 {
+                    rotate_hue(&mut pair.input, angle);
+                }
+                pair.input.iter_mut().for_each(|v| *v = (*v - mean) * factor + mean + offset);
+            }
+            Augmentation::GaussianNoise { std } => {
+                pair.input.iter_mut().for_each(|v| *v += standard_normal(rng) * std);
+            }
+            Augmentation::Elastic { alpha, sigma } => {
+                let (h, w) = (pair.height, pair.width);
+                let mut field = || {
+                    let noise = (0 .. h * w).map(|_| rng.gen_range(-1.0f32 ..= 1.0)).collect::<Vec<_>>();
+                    gaussian_blur(&noise, h, w, sigma)
+                };
+                let dy = field();
+                let dx = field();
+                warp(pair, |y, x| {
+                    let i = y as usize * w + x as usize;
+                    (y + alpha * dy[i], x + alpha * dx[i])
+                });
+            }
+        }
+    }
+}
+
+//Moves whole pixels, f gives the source (y, x) for every destination pixel of a height x width output
+fn remap(pair: &mut ImagePair, height: usize, width: usize, f: impl Fn(isize, isize) -> (isize, isize)) {
+    let src_width = pair.width;
+    let permute = |values: &[f32], channels: usize| {
+        let mut out = Vec::with_capacity(values.len());
+        for y in 0 .. height as isize {
+            for x in 0 .. width as isize {
+                let (sy, sx) = f(y, x);
+                let start = (sy as usize * src_width + sx as usize) * channels;
+                out.extend_from_slice(&values[start .. start + channels]);
+            }
+        }
+        out
+    };
+    pair.input = permute(&pair.input, pair.input_channels);
+    pair.target = permute(&pair.target, pair.target_channels);
+    pair.height = height;
+    pair.width = width;
+}
+
+//Resamples input and target with the same coordinates, f gives the fractional source (y, x) for every destination pixel
+pub(crate) fn warp(pair: &mut ImagePair, f: impl Fn(f32, f32) -> (f32, f32)) {
+    let coords = (0 .. pair.height)
+        .flat_map(|y| (0 .. pair.width).map(move |x| (y as f32, x as f32)))
+        .map(|(y, x)| f(y, x))
+        .collect::<Vec<_>>();
+    pair.input = resample(&pair.input, pair.height, pair.width, pair.input_channels, &coords);
+    pair.target = if pair.target_is_label {
+        resample_nearest(&pair.target, pair.height, pair.width, pair.target_channels, &coords)
+    } else {
+        resample(&pair.target, pair.height, pair.width, pair.target_channels, &coords)
+    };
+}
+
+//Nearest neighbour lookup of every coordinate, out of bounds coordinates are mirrored back into the image
+pub(crate) fn resample_nearest(values: &[f32], height: usize, width: usize, channels: usize, coords: &[(f32, f32)]) -> Vec<f32> {
+    let mut out = Vec::with_capacity(coords.len() * channels);
+    for &(y, x) in coords.iter() {
+        let row = mirror_index(y.round() as isize, height);
+        let col = mirror_index(x.round() as isize, width);
+        let start = (row * width + col) * channels;
+        out.extend_from_slice(&values[start .. start + channels]);
+    }
+    out
+}
+
+//Bilinear lookup of every coordinate, out of bounds coordinates are mirrored back into the image
+pub(crate) fn resample(values: &[f32], height: usize, width: usize, channels: usize, coords: &[(f32, f32)]) -> Vec<f32> {
+    let mut out = Vec::with_capacity(coords.len() * channels);
+    for &(y, x) in coords.iter() {
+        let (y0, x0) = (y.floor(), x.floor());
+        let (fy, fx) = (y - y0, x - x0);
+        let (y0, x0) = (y0 as isize, x0 as isize);
+        let rows = [mirror_index(y0, height), mirror_index(y0 + 1, height)];
+        let cols = [mirror_index(x0, width), mirror_index(x0 + 1, width)];
+        let weights = [(1.0 - fy) * (1.0 - fx), (1.0 - fy) * fx, fy * (1.0 - fx), fy * fx];
+        for c in 0 .. channels {
+            let corners = [(rows[0], cols[0]), (rows[0], cols[1]), (rows[1], cols[0]), (rows[1], cols[1])];
+            let value = corners.iter().zip(weights.iter())
+                .map(|(&(ry, rx), weight)| values[(ry * width + rx) * channels + c] * weight)
+                .sum();
+            out.push(value);
+        }
+    }
+    out
+}
+
+//Rotates rgb pixels around the grey axis in YIQ space
+fn rotate_hue(values: &mut [f32], angle: f32) {
+    let (sin, cos) = angle.sin_cos();
+    for pixel in values.chunks_mut(3) {
+        let (r, g, b) = (pixel[0], pixel[1], pixel[2]);
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        let i = 0.596 * r - 0.274 * g - 0.322 * b;
+        let q = 0.211 * r - 0.523 * g + 0.312 * b;
+        let (i, q) = (cos * i - sin * q, sin * i + cos * q);
+        pixel[0] = y + 0.956 * i + 0.621 * q;
+        pixel[1] = y - 0.272 * i - 0.647 * q;
+        pixel[2] = y - 1.106 * i + 1.703 * q;
+    }
+}
+
+//Separable gaussian blur of a single channel image with mirrored borders
+fn gaussian_blur(values: &[f32], height: usize, width: usize, sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel = (-radius ..= radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect::<Vec<_>>();
+    let total = kernel.iter().sum::<f32>();
+    let kernel = kernel.into_iter().map(|k| k / total).collect::<Vec<_>>();
+
+    let mut horizontal = vec![0.0; values.len()];
+    for y in 0 .. height {
+        for x in 0 .. width {
+            horizontal[y * width + x] = kernel.iter().enumerate()
+                .map(|(k, weight)| weight * values[y * width + mirror_index(x as isize + k as isize - radius, width)])
+                .sum();
+        }
+    }
+    let mut blurred = vec![0.0; values.len()];
+    for y in 0 .. height {
+        for x in 0 .. width {
+            blurred[y * width + x] = kernel.iter().enumerate()
+                .map(|(k, weight)| weight * horizontal[mirror_index(y as isize + k as isize - radius, height) * width + x])
+                .sum();
+        }
+    }
+    blurred
+}
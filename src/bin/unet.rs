@@ -0,0 +1,399 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use descent::prelude::*;
+use descent_unet_example::{checkpoint::Checkpoint, config::*, data, import::{self, NameMap}, init::InitConfig, network::Network, npy, onnx, reference, tiling::TiledInference, seed::RunSeed, train, unet::WithAttentionMaps};
+use std::{fs, path::PathBuf};
+
+#[derive(Parser)]
+#[command(name = "unet", about = "Train, run and inspect UNets built with descent")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    ///Train on image pairs and write a checkpoint after every epoch
+    Train(TrainArgs),
+    ///Run a trained UNet over whole images with overlap-tile inference
+    Predict(PredictArgs),
+    ///Print the configuration and parameter statistics of a checkpoint
+    InspectCheckpoint(InspectArgs),
+    ///Write the parameters of a checkpoint to a directory
+    Export(ExportArgs),
+    ///Turn weights trained elsewhere (safetensors) into a checkpoint
+    Import(ImportArgs),
+}
+
+///Maps onto the arguments of UNet::new
+#[derive(Args)]
+struct ModelArgs {
+    #[arg(long, default_value_t = 1)]
+    inputs: usize,
+    #[arg(long, default_value_t = 3)]
+    outputs: usize,
+    #[arg(long, default_value_t = 2)]
+    depth: usize,
+    #[arg(long, default_value_t = 16)]
+    width: usize,
+    #[arg(long, default_value_t = 3)]
+    kernel_size: usize,
+    ///Residual blocks with 1x1 projection shortcuts instead of plain conv pairs
+    #[arg(long)]
+    residual: bool,
+    ///Attention gates on the skip connections
+    #[arg(long)]
+    attention: bool,
+    ///Auxiliary predictions from every inner level that are trained against the target as well
+    #[arg(long)]
+    deep_supervision: bool,
+    ///Loss weight of each auxiliary prediction
+    #[arg(long, default_value_t = 1.0)]
+    auxiliary_weight: f32,
+    #[arg(long, value_enum, default_value_t = DownsamplingArg::Max)]
+    downsampling: DownsamplingArg,
+    ///Window and stride of the downsampling between levels
+    #[arg(long, default_value_t = 2)]
+    pool_size: usize,
+    ///Dropout rate during training, 0 turns it off
+    #[arg(long, default_value_t = 0.0)]
+    dropout: f32,
+    ///Drop whole channels instead of single activations
+    #[arg(long)]
+    spatial_dropout: bool,
+    ///Comma separated levels that get dropout, 0 is the outermost one. Defaults to the innermost level
+    #[arg(long, value_delimiter = ',')]
+    dropout_levels: Vec<usize>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DownsamplingArg {
+    ///Max pooling
+    Max,
+    ///Average pooling
+    Average,
+    ///Learned convolution with a stride of --pool-size
+    StridedConv,
+}
+
+///Adam hyperparameters
+#[derive(Args)]
+struct OptimizerArgs {
+    #[arg(long, default_value_t = 0.001)]
+    learning_rate: f32,
+    #[arg(long, default_value_t = 0.95)]
+    beta1: f32,
+    #[arg(long, default_value_t = 0.99)]
+    beta2: f32,
+    #[arg(long, default_value_t = 1.0E-8)]
+    epsilon: f32,
+}
+
+#[derive(Args)]
+struct TrainArgs {
+    ///Experiment config (.toml or .json), replaces the model, optimizer and dataset flags
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    model: ModelArgs,
+    #[command(flatten)]
+    optimizer: OptimizerArgs,
+    ///Input images, converted to --inputs channels
+    #[arg(long, required_unless_present = "config", num_args = 1..)]
+    images: Vec<PathBuf>,
+    ///Target images, converted to --outputs channels. Defaults to the input images (colorization)
+    #[arg(long, num_args = 1..)]
+    targets: Vec<PathBuf>,
+    #[arg(long, default_value_t = 128)]
+    tile_size: usize,
+    #[arg(long, default_value_t = 1)]
+    batch_size: usize,
+    ///Threads decoding and augmenting batches in the background
+    #[arg(long, default_value_t = 2)]
+    workers: usize,
+    ///Batches prepared ahead of the training loop
+    #[arg(long, default_value_t = 4)]
+    prefetch: usize,
+    #[arg(long, default_value_t = 60)]
+    epochs: usize,
+    #[arg(long, default_value_t = 10)]
+    steps_per_epoch: usize,
+    ///Normalize every input channel by its mean and std over the images
+    #[arg(long)]
+    normalize: bool,
+    ///Write checkpoints as .safetensors instead of .unet
+    #[arg(long)]
+    safetensors: bool,
+    ///Seed for weight init, tile sampling, augmentation and the graph, also overrides the seed of --config
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long, default_value = "runs")]
+    output_dir: PathBuf,
+}
+
+#[derive(Args)]
+struct PredictArgs {
+    #[arg(long)]
+    checkpoint: PathBuf,
+    #[arg(long, required = true, num_args = 1..)]
+    images: Vec<PathBuf>,
+    #[arg(long, default_value_t = 128)]
+    tile_size: usize,
+    #[arg(long, default_value_t = 4)]
+    batch_size: usize,
+    #[arg(long, default_value = "predictions")]
+    output_dir: PathBuf,
+    ///Run the checkpoint with the plain Rust reference implementation instead of descent
+    #[arg(long)]
+    cpu: bool,
+    ///Also save the attention map of every gated level as <image>_attention_<level>.png, outermost level first
+    #[arg(long)]
+    attention_maps: bool,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    checkpoint: PathBuf,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    ///One little endian f32 file per parameter plus a shapes.txt
+    Raw,
+    ///One .npy file per parameter
+    Npy,
+    ///All parameters in a single parameters.npz
+    Npz,
+    ///ONNX model.onnx for NCHW tiles of --tile-size
+    Onnx,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    #[arg(long)]
+    checkpoint: PathBuf,
+    #[arg(long, value_enum, default_value_t = ExportFormat::Raw)]
+    format: ExportFormat,
+    ///Input tile size of the ONNX model
+    #[arg(long, default_value_t = 128)]
+    tile_size: usize,
+    #[arg(long, default_value = "export")]
+    output_dir: PathBuf,
+}
+
+#[derive(Args)]
+struct ImportArgs {
+    ///safetensors file with the weights
+    #[arg(long)]
+    weights: PathBuf,
+    ///TOML or JSON experiment config describing the network the weights belong to
+    #[arg(long)]
+    config: PathBuf,
+    ///TOML or JSON table of name prefixes to rename, see import::NameMap
+    #[arg(long)]
+    names: Option<PathBuf>,
+    #[arg(long, default_value = "imported.unet")]
+    output: PathBuf,
+}
+
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Train(args) => train(args),
+        Command::Predict(args) => predict(args),
+        Command::InspectCheckpoint(args) => inspect(args),
+        Command::Export(args) => export(args),
+        Command::Import(args) => import(args),
+    }
+}
+
+fn train(args: TrainArgs) {
+    let mut config = match args.config.as_ref() {
+        Some(path) => ExperimentConfig::load(path).unwrap_or_else(|e| panic!("{e}")),
+        None => {
+            let config = ExperimentConfig {
+                model: ModelConfig {
+                    architecture: Architecture::Unet,
+                    inputs: args.model.inputs,
+                    outputs: args.model.outputs,
+                    depth: args.model.depth,
+                    width: args.model.width,
+                    kernelsize: args.model.kernel_size,
+                    init: InitConfig::default(),
+                    normalization: None,
+                    unet: UNetConfig {
+                        block: if args.model.residual { Block::Residual } else { Block::Plain },
+                        attention: args.model.attention,
+                        deep_supervision: args.model.deep_supervision,
+                        auxiliary_weight: args.model.auxiliary_weight,
+                        downsampling: match args.model.downsampling {
+                            DownsamplingArg::Max => Downsampling::Max,
+                            DownsamplingArg::Average => Downsampling::Average,
+                            DownsamplingArg::StridedConv => Downsampling::StridedConv,
+                        },
+                        pool_size: args.model.pool_size,
+                    },
+                    dropout: (args.model.dropout > 0.0).then(|| DropoutConfig {
+                        kind: if args.model.spatial_dropout { DropoutKind::Spatial } else { DropoutKind::Element },
+                        rate: args.model.dropout,
+                        levels: if args.model.dropout_levels.is_empty() { vec![args.model.depth] } else { args.model.dropout_levels.clone() },
+                    }),
+                },
+                loss: LossConfig::Mse,
+                optimizer: OptimizerConfig {
+                    learning_rate: args.optimizer.learning_rate,
+                    beta1: args.optimizer.beta1,
+                    beta2: args.optimizer.beta2,
+                    epsilon: args.optimizer.epsilon,
+                },
+                schedule: ScheduleConfig {
+                    steps_per_epoch: args.steps_per_epoch,
+                    checkpoint_every: 1,
+                    checkpoint_format: if args.safetensors { CheckpointFormat::Safetensors } else { CheckpointFormat::Native },
+                },
+                dataset: DatasetConfig {
+                    images: args.images,
+                    targets: args.targets,
+                    tile_size: args.tile_size,
+                    batch_size: args.batch_size,
+                    target_is_label: false,
+                    colorize: None,
+                    normalize: args.normalize,
+                    workers: args.workers,
+                    prefetch: args.prefetch,
+                },
+                augmentation: Vec::new(),
+                epochs: args.epochs,
+                seed: None,
+            };
+            config.validate().unwrap_or_else(|e| panic!("{e}"));
+            config
+        }
+    };
+    if args.seed.is_some() {
+        config.seed = args.seed;
+    }
+    train::train(&config, &args.output_dir);
+}
+
+fn predict(args: PredictArgs) {
+    let checkpoint = Checkpoint::load(&args.checkpoint).expect("Could not load checkpoint");
+    let config = &checkpoint.config.model;
+    let tile_size = args.tile_size;
+    let attention_levels = if args.attention_maps { config.attention_levels() } else { 0 };
+    assert!(!args.attention_maps || attention_levels > 0, "The checkpoint has no attention gates");
+
+    //descent is only set up when the network runs on the GPU
+    let mut gpu = (!args.cpu).then(|| {
+        let mut env = Environment::new();
+        let rng = RunSeed::from_config(checkpoint.config.seed).graph_rng();
+        let network = Network::new(&mut env, config);
+        let parameters = network.parameters(&mut env, [1, tile_size, tile_size, config.inputs]);
+        checkpoint.upload(&mut env, &network.parameter_names(), &parameters).expect("Checkpoint does not match the network");
+        let tiled = match &network {
+            Network::UNet(unet) if attention_levels > 0 => TiledInference::new(&mut env, &WithAttentionMaps(unet), args.batch_size, tile_size, tile_size, config.inputs),
+            _ => TiledInference::new(&mut env, &network, args.batch_size, tile_size, tile_size, config.inputs),
+        };
+        (env, tiled, rng)
+    });
+    //Maps a normalized HWC image to the HWC prediction
+    let mut infer = |input: &[f32], height: usize, width: usize| match gpu.as_mut() {
+        Some((env, tiled, rng)) => tiled.run(env, input, height, width, rng),
+        None => reference::predict(&checkpoint, input, height, width, tile_size, attention_levels > 0).unwrap_or_else(|e| panic!("Could not run checkpoint: {e}")),
+    };
+
+    fs::create_dir_all(&args.output_dir).expect("Could not create output directory");
+    for path in args.images.iter() {
+        let file_name = path.file_stem().unwrap().to_string_lossy();
+        let output_path = args.output_dir.join(format!("{file_name}_prediction.png"));
+        //Colorization networks predict in their own colour space, the lightness comes from the input
+        let data::PredictionInput { input, height, width, colorized } = data::PredictionInput::load(&checkpoint.config, path)
+            .unwrap_or_else(|e| panic!("Could not open image: {e}"));
+        let output = infer(&input, height, width);
+
+        //Attention maps are extra channels behind the prediction
+        let channels = config.outputs + attention_levels;
+        let prediction = output.chunks(channels).flat_map(|pixel| &pixel[.. config.outputs]).copied().collect::<Vec<_>>();
+        match colorized {
+            Some((space, lightness)) => data::save_image(&output_path, &space.combine(&lightness, &prediction), height, width, 3),
+            None => data::save_image(&output_path, &prediction, height, width, config.outputs),
+        }.expect("Could not save prediction");
+        for level in 0 .. attention_levels {
+            let map = output.chunks(channels).map(|pixel| pixel[config.outputs + level]).collect::<Vec<_>>();
+            let map_path = args.output_dir.join(format!("{file_name}_attention_{level}.png"));
+            data::save_image(&map_path, &map, height, width, 1).expect("Could not save attention map");
+        }
+        eprintln!("{} => {}", path.display(), output_path.display());
+    }
+}
+
+fn inspect(args: InspectArgs) {
+    let checkpoint = Checkpoint::load(&args.checkpoint).expect("Could not load checkpoint");
+    println!("{}", checkpoint.config.to_json());
+    println!("Epoch {}", checkpoint.epoch);
+    let mut total = 0;
+    for tensor in checkpoint.tensors.iter() {
+        let count = tensor.values.len();
+        let min = tensor.values.iter().cloned().fold(f32::INFINITY, f32::min);
+        let max = tensor.values.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+        let mean = tensor.values.iter().sum::<f32>() / count as f32;
+        println!("{:<24} shape {:?} ({count} floats) min {min:.4} max {max:.4} mean {mean:.4}", tensor.name, tensor.shape);
+        total += count;
+    }
+    println!("{total} parameters in total");
+}
+
+fn export(args: ExportArgs) {
+    let checkpoint = Checkpoint::load(&args.checkpoint).expect("Could not load checkpoint");
+    fs::create_dir_all(&args.output_dir).expect("Could not create output directory");
+    match args.format {
+        ExportFormat::Raw => {
+            let mut shapes = String::new();
+            for tensor in checkpoint.tensors.iter() {
+                fs::write(args.output_dir.join(format!("{}.f32", tensor.name)), bytemuck::cast_slice(&tensor.values)).expect("Could not write tensor");
+                shapes += &format!("{} {:?}\n", tensor.name, tensor.shape);
+            }
+            fs::write(args.output_dir.join("shapes.txt"), shapes).expect("Could not write shapes");
+        }
+        ExportFormat::Npy => {
+            for tensor in checkpoint.tensors.iter() {
+                npy::write(args.output_dir.join(format!("{}.npy", tensor.name)), &tensor.shape, &tensor.values, npy::Dtype::F32).expect("Could not write tensor");
+            }
+        }
+        ExportFormat::Npz => {
+            npy::write_npz(args.output_dir.join("parameters.npz"), &checkpoint.tensors, npy::Dtype::F32).expect("Could not write parameters");
+        }
+        ExportFormat::Onnx => {
+            let model = onnx::export(&checkpoint, args.tile_size, args.tile_size).unwrap_or_else(|e| panic!("Could not convert checkpoint: {e}"));
+            onnx::save(&model, args.output_dir.join("model.onnx")).expect("Could not write model");
+        }
+    }
+    eprintln!("Exported {} tensors to {}", checkpoint.tensors.len(), args.output_dir.display());
+}
+
+fn import(args: ImportArgs) {
+    let config = ExperimentConfig::load(&args.config).unwrap_or_else(|e| panic!("{e}"));
+    let map = args.names.as_ref().map_or_else(NameMap::default, |path| NameMap::load(path).unwrap_or_else(|e| panic!("{e}")));
+
+    let mut env = Environment::new();
+    let network = Network::new(&mut env, &config.model);
+    let names = network.parameter_names();
+    let tile = config.dataset.tile_size;
+    let shapes = network.parameters(&mut env, [1, tile, tile, config.model.inputs])
+        .iter()
+        .map(|param| param.shape().to_vec())
+        .collect::<Vec<_>>();
+
+    let import = import::import_safetensors(&args.weights, &map, &names, &shapes).unwrap_or_else(|e| panic!("Could not import weights: {e}"));
+    for name in import.unmatched.iter() {
+        eprintln!("Unmatched tensor {name}");
+    }
+    for name in import.missing.iter() {
+        eprintln!("Missing parameter {name}");
+    }
+    if !import.missing.is_empty() {
+        panic!("{} parameters have no weights, extend the name map", import.missing.len());
+    }
+    let checkpoint = Checkpoint { config, epoch: 0, tensors: import.tensors };
+    checkpoint.save(&args.output).expect("Could not save checkpoint");
+    eprintln!("Imported {} tensors ({} unmatched) into {}", checkpoint.tensors.len(), import.unmatched.len(), args.output.display());
+}
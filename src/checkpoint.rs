@@ -0,0 +1,194 @@
+use crate::config::ExperimentConfig;
+use descent::prelude::*;
+use safetensors::{tensor::{Dtype, TensorView}, SafeTensors};
+use std::{collections::HashMap, fs::{self, File}, io::{self, BufReader, BufWriter, Read, Take, Write}, path::Path};
+
+//Checkpoint layout (all integers little endian):
+//magic, version, length of the experiment config JSON, the JSON, epoch, tensor count,
+//then per tensor: name length, name, rank, dims, f32 values.
+//Paths ending in .safetensors use that format instead, with the config JSON, the epoch and the
+//parameter order as metadata
+const MAGIC: &[u8; 8] = b"UNETCKPT";
+const VERSION: u32 = 2;
+
+pub struct Tensor {
+    pub name: String,
+    pub shape: Vec<usize>,
+    pub values: Vec<f32>,
+}
+
+pub struct Checkpoint {
+    pub config: ExperimentConfig,
+    pub epoch: usize,
+    pub tensors: Vec<Tensor>,
+}
+
+impl Checkpoint {
+    //Reads the current parameter values out of the environment, names and parameters have to line up
+    pub fn from_parameters(env: &mut Environment, config: ExperimentConfig, epoch: usize, names: &[String], parameters: &[Parameter]) -> Self {
+        assert_eq!(names.len(), parameters.len(), "Every parameter needs a name");
+        let tensors = names.iter().zip(parameters.iter())
+            .map(|(name, param)| Tensor {
+                name: name.clone(),
+                shape: param.shape().to_vec(),
+                values: env.read_parameter_to_vec(param),
+            })
+            .collect();
+        Self { config, epoch, tensors }
+    }
+
+    //Writes the stored values into the parameters of a freshly built network
+    pub fn upload(&self, env: &mut Environment, names: &[String], parameters: &[Parameter]) -> io::Result<()> {
+        for (name, param) in names.iter().zip(parameters.iter()) {
+            let tensor = self.get(name)
+                .ok_or_else(|| invalid_data(format!("Parameter {name} not found in checkpoint")))?;
+            if tensor.shape != param.shape().to_vec() {
+                return Err(invalid_data(format!("Parameter {name} has shape {:?}, checkpoint has {:?}", param.shape(), tensor.shape)));
+            }
+            let mut writer = env.writer(param);
+            writer.write_all(bytemuck::cast_slice(&tensor.values))?;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Tensor> {
+        self.tensors.iter().find(|tensor| tensor.name == name)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if is_safetensors(path) {
+            return self.save_safetensors(path);
+        }
+        let mut w = BufWriter::new(File::create(path)?);
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        let config = self.config.to_json();
+        write_u64(&mut w, config.len())?;
+        w.write_all(config.as_bytes())?;
+        write_u64(&mut w, self.epoch)?;
+        write_u64(&mut w, self.tensors.len())?;
+        for tensor in self.tensors.iter() {
+            write_u64(&mut w, tensor.name.len())?;
+            w.write_all(tensor.name.as_bytes())?;
+            write_u64(&mut w, tensor.shape.len())?;
+            for dim in tensor.shape.iter() {
+                write_u64(&mut w, *dim)?;
+            }
+            w.write_all(bytemuck::cast_slice(&tensor.values))?;
+        }
+        w.flush()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if is_safetensors(path) {
+            return Self::load_safetensors(path);
+        }
+        //Every length read from the file is checked against what is left of it before allocating
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut r = BufReader::new(file).take(size);
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("Not a UNet checkpoint".to_string()));
+        }
+        let mut version = [0u8; 4];
+        r.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(invalid_data(format!("Unsupported checkpoint version {version}")));
+        }
+        let length = read_u64(&mut r)?;
+        let mut config = vec![0u8; remaining(&r, length, 1, "config")?];
+        r.read_exact(&mut config)?;
+        let config = String::from_utf8(config).map_err(|e| invalid_data(e.to_string()))?;
+        let config = ExperimentConfig::from_json(&config).map_err(|e| invalid_data(e.to_string()))?;
+        let epoch = read_u64(&mut r)?;
+        let count = read_u64(&mut r)?;
+        //Every tensor takes at least its name length and rank
+        let mut tensors = Vec::with_capacity(remaining(&r, count, 16, "tensor list")? / 16);
+        for _ in 0 .. count {
+            let length = read_u64(&mut r)?;
+            let mut name = vec![0u8; remaining(&r, length, 1, "tensor name")?];
+            r.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|e| invalid_data(e.to_string()))?;
+            let rank = read_u64(&mut r)?;
+            remaining(&r, rank, 8, &name)?;
+            let shape = (0 .. rank).map(|_| read_u64(&mut r)).collect::<io::Result<Vec<_>>>()?;
+            let len = shape.iter().try_fold(1usize, |len, dim| len.checked_mul(*dim))
+                .ok_or_else(|| invalid_data(format!("{name} has an impossible shape {shape:?}")))?;
+            let mut values = vec![0.0f32; remaining(&r, len, 4, &name)? / 4];
+            r.read_exact(bytemuck::cast_slice_mut(&mut values))?;
+            tensors.push(Tensor { name, shape, values });
+        }
+        Ok(Self { config, epoch, tensors })
+    }
+
+    fn save_safetensors(&self, path: &Path) -> io::Result<()> {
+        let views = self.tensors.iter()
+            .map(|tensor| {
+                let view = TensorView::new(Dtype::F32, tensor.shape.clone(), bytemuck::cast_slice(&tensor.values))
+                    .map_err(|e| invalid_data(format!("{}: {e}", tensor.name)))?;
+                Ok((tensor.name.as_str(), view))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let order = self.tensors.iter().map(|tensor| tensor.name.as_str()).collect::<Vec<_>>();
+        let metadata = HashMap::from([
+            ("config".to_string(), self.config.to_json()),
+            ("epoch".to_string(), self.epoch.to_string()),
+            ("parameters".to_string(), serde_json::to_string(&order).unwrap()),
+        ]);
+        safetensors::serialize_to_file(views, &Some(metadata), path).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    fn load_safetensors(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        let (_, header) = SafeTensors::read_metadata(&bytes).map_err(|e| invalid_data(e.to_string()))?;
+        let metadata = header.metadata().clone().unwrap_or_default();
+        let field = |key: &str| metadata.get(key).ok_or_else(|| invalid_data(format!("Checkpoint metadata has no {key}")));
+        let config = ExperimentConfig::from_json(field("config")?).map_err(|e| invalid_data(e.to_string()))?;
+        let epoch = field("epoch")?.parse().map_err(|_| invalid_data("Checkpoint epoch is not a number".to_string()))?;
+        let order: Vec<String> = serde_json::from_str(field("parameters")?).map_err(|e| invalid_data(e.to_string()))?;
+
+        let file = SafeTensors::deserialize(&bytes).map_err(|e| invalid_data(e.to_string()))?;
+        let mut tensors = file.tensors().into_iter()
+            .map(|(name, view)| {
+                if view.dtype() != Dtype::F32 {
+                    return Err(invalid_data(format!("{name} is {:?}, checkpoints are f32", view.dtype())));
+                }
+                let values = view.data().chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect();
+                Ok(Tensor { name, shape: view.shape().to_vec(), values })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        //safetensors does not keep the order, tensors missing from the list go last
+        tensors.sort_by_key(|tensor| (order.iter().position(|name| *name == tensor.name).unwrap_or(order.len()), tensor.name.clone()));
+        Ok(Self { config, epoch, tensors })
+    }
+}
+
+fn is_safetensors(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "safetensors")
+}
+
+fn write_u64(w: &mut impl Write, value: usize) -> io::Result<()> {
+    w.write_all(&(value as u64).to_le_bytes())
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<usize> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes) as usize)
+}
+
+//Byte size of count elements of the given size, if the rest of the file holds that many
+fn remaining<R>(r: &Take<R>, count: usize, size: usize, what: &str) -> io::Result<usize> {
+    count.checked_mul(size)
+        .filter(|bytes| *bytes as u64 <= r.limit())
+        .ok_or_else(|| invalid_data(format!("Checkpoint is truncated, {what} does not fit into the file")))
+}
+
+pub(crate) fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
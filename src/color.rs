@@ -0,0 +1,115 @@
+use serde::{Deserialize, Serialize};
+
+//Colour space a colorization network predicts in. The input is always a single lightness channel,
+//Lab and YCbCr only have to predict the two chroma channels on top of it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    //Regress all of r, g and b from the luma, like the colorizing examples
+    #[default]
+    Rgb,
+    //CIELAB (D65), L scaled to 0..1 and a, b divided by 100
+    Lab,
+    //Full range BT.601, Cb and Cr centered around zero
+    #[serde(rename = "ycbcr")]
+    YCbCr,
+}
+
+impl ColorSpace {
+    pub fn target_channels(self) -> usize {
+        match self {
+            ColorSpace::Rgb => 3,
+            ColorSpace::Lab | ColorSpace::YCbCr => 2,
+        }
+    }
+
+    //Splits an rgb image in 0..1 into the lightness input and the target of the network
+    pub fn split(self, rgb: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        let pixels = rgb.len() / 3;
+        let mut lightness = Vec::with_capacity(pixels);
+        let mut target = Vec::with_capacity(pixels * self.target_channels());
+        for pixel in rgb.chunks(3) {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]];
+            match self {
+                ColorSpace::Rgb => {
+                    //Same coefficients as image's to_luma32f
+                    lightness.push(0.2126 * r + 0.7152 * g + 0.0722 * b);
+                    target.extend_from_slice(pixel);
+                }
+                ColorSpace::Lab => {
+                    let [l, a, b] = rgb_to_lab([r, g, b]);
+                    lightness.push(l);
+                    target.extend_from_slice(&[a, b]);
+                }
+                ColorSpace::YCbCr => {
+                    let [y, cb, cr] = rgb_to_ycbcr([r, g, b]);
+                    lightness.push(y);
+                    target.extend_from_slice(&[cb, cr]);
+                }
+            }
+        }
+        (lightness, target)
+    }
+
+    //Puts the lightness input and a prediction back together into rgb in 0..1
+    pub fn combine(self, lightness: &[f32], prediction: &[f32]) -> Vec<f32> {
+        if self == ColorSpace::Rgb {
+            return prediction.iter().map(|v| v.clamp(0.0, 1.0)).collect();
+        }
+        lightness.iter().zip(prediction.chunks(2))
+            .flat_map(|(&l, chroma)| {
+                let rgb = match self {
+                    ColorSpace::Lab => lab_to_rgb([l, chroma[0], chroma[1]]),
+                    _ => ycbcr_to_rgb([l, chroma[0], chroma[1]]),
+                };
+                rgb.map(|v| v.clamp(0.0, 1.0))
+            })
+            .collect()
+    }
+}
+
+//Mean squared error and PSNR (in dB) of an rgb prediction against the original, both in 0..1
+pub fn rgb_error(prediction: &[f32], original: &[f32]) -> (f32, f32) {
+    let mse = prediction.iter().zip(original.iter()).map(|(p, o)| (p - o) * (p - o)).sum::<f32>() / original.len() as f32;
+    (mse, -10.0 * mse.log10())
+}
+
+fn rgb_to_ycbcr([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    [y, 0.564 * (b - y), 0.713 * (r - y)]
+}
+
+fn ycbcr_to_rgb([y, cb, cr]: [f32; 3]) -> [f32; 3] {
+    [y + 1.403 * cr, y - 0.344 * cb - 0.714 * cr, y + 1.773 * cb]
+}
+
+//D65 white point
+const WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+const DELTA: f32 = 6.0 / 29.0;
+
+fn rgb_to_lab(rgb: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|c| if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) });
+    let xyz = [
+        0.4124564 * r + 0.3575761 * g + 0.1804375 * b,
+        0.2126729 * r + 0.7151522 * g + 0.072175 * b,
+        0.0193339 * r + 0.119192 * g + 0.9503041 * b,
+    ];
+    let f = |t: f32| if t > DELTA.powi(3) { t.cbrt() } else { t / (3.0 * DELTA * DELTA) + 4.0 / 29.0 };
+    let [fx, fy, fz] = [f(xyz[0] / WHITE[0]), f(xyz[1] / WHITE[1]), f(xyz[2] / WHITE[2])];
+    [(116.0 * fy - 16.0) / 100.0, 5.0 * (fx - fy), 2.0 * (fy - fz)]
+}
+
+fn lab_to_rgb([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let fy = (l * 100.0 + 16.0) / 116.0;
+    let [fx, fz] = [fy + a / 5.0, fy - b / 2.0];
+    let f_inv = |t: f32| if t > DELTA { t.powi(3) } else { 3.0 * DELTA * DELTA * (t - 4.0 / 29.0) };
+    let [x, y, z] = [f_inv(fx) * WHITE[0], f_inv(fy) * WHITE[1], f_inv(fz) * WHITE[2]];
+    [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.969266 * x + 1.8760108 * y + 0.041556 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ].map(|c| {
+        let c = c.max(0.0);
+        if c <= 0.0031308 { 12.92 * c } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+    })
+}
@@ -0,0 +1,418 @@
+use crate::{augment::Augmentation, color::ColorSpace, init::InitConfig};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, path::{Path, PathBuf}};
+
+//Everything needed to reproduce a training run, loaded from a TOML or JSON file
+//and embedded into every checkpoint
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExperimentConfig {
+    pub model: ModelConfig,
+    #[serde(default)]
+    pub loss: LossConfig,
+    #[serde(default)]
+    pub optimizer: OptimizerConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    pub dataset: DatasetConfig,
+    //Applied in order to every training tile, see augment.rs
+    #[serde(default)]
+    pub augmentation: Vec<Augmentation>,
+    pub epochs: usize,
+    #[serde(default)]
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Architecture {
+    #[default]
+    Unet,
+    Fcn,
+    //UNet++, nested dense skip pathways between the encoder and the decoder
+    UnetPlusPlus,
+}
+
+//Arguments of UNet::new, UNetPlusPlus::new and FCN::new, plus how to initialize the weights
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelConfig {
+    #[serde(default)]
+    pub architecture: Architecture,
+    pub inputs: usize,
+    pub outputs: usize,
+    pub depth: usize,
+    pub width: usize,
+    #[serde(default = "default_kernelsize")]
+    pub kernelsize: usize,
+    #[serde(default)]
+    pub init: InitConfig,
+    //Input normalization, computed from the dataset at the start of training for multi-band rasters
+    #[serde(default)]
+    pub normalization: Option<Normalization>,
+    //Options for the UNet and UNet++ architectures
+    #[serde(default)]
+    pub unet: UNetConfig,
+    #[serde(default)]
+    pub dropout: Option<DropoutConfig>,
+}
+
+impl ModelConfig {
+    //Number of UNet levels with an attention gate, every level but the innermost one has one
+    pub fn attention_levels(&self) -> usize {
+        if self.architecture == Architecture::Unet && self.unet.attention { self.depth } else { 0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UNetConfig {
+    #[serde(default)]
+    pub block: Block,
+    //Additive attention gates (Attention U-Net) that weight the skip features by the inner level's output
+    #[serde(default)]
+    pub attention: bool,
+    //Extra output heads that are trained against the target as well, prediction only uses the last one.
+    //UNet puts one on the output of every inner level, UNet++ on every top node of the nested decoder
+    #[serde(default)]
+    pub deep_supervision: bool,
+    //Loss weight of every extra head, the prediction has weight 1
+    #[serde(default = "default_auxiliary_weight")]
+    pub auxiliary_weight: f32,
+    //How the encoder gets from one level to the next
+    #[serde(default)]
+    pub downsampling: Downsampling,
+    //Window and stride of the downsampling, every level is pool_size times smaller than the one above
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+}
+
+impl Default for UNetConfig {
+    fn default() -> Self {
+        Self {
+            block: Block::Plain,
+            attention: false,
+            deep_supervision: false,
+            auxiliary_weight: default_auxiliary_weight(),
+            downsampling: Downsampling::Max,
+            pool_size: default_pool_size(),
+        }
+    }
+}
+
+//What the two convs on either side of every UNet level are wrapped in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Block {
+    #[default]
+    Plain,
+    //conv-act-conv plus a 1x1 projection of the block input, cropped to the valid output, before the last activation
+    Residual,
+}
+
+//Every variant rounds down, trailing rows and columns that do not fill a window are dropped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Downsampling {
+    #[default]
+    Max,
+    Average,
+    //Learned pool_size x pool_size conv with stride pool_size and a leaky relu, keeps the channel count
+    StridedConv,
+}
+
+//Dropout after the encoder block of the listed UNet levels (0 is the outermost one, the innermost one is the
+//bottleneck), the encoder nodes (i, 0) of UNet++, or the listed hidden convs of the FCN
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DropoutConfig {
+    #[serde(default)]
+    pub kind: DropoutKind,
+    pub rate: f32,
+    pub levels: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DropoutKind {
+    //Every activation on its own
+    #[default]
+    Element,
+    //Whole channels, neighbouring pixels are too correlated for element dropout to do much in conv layers
+    Spatial,
+}
+
+//Per-band statistics, every input band is mapped to (value - mean) / std
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Normalization {
+    pub mean: Vec<f32>,
+    pub std: Vec<f32>,
+}
+
+impl Normalization {
+    pub fn apply(&self, values: &mut [f32]) {
+        for pixel in values.chunks_mut(self.mean.len()) {
+            for ((v, mean), std) in pixel.iter_mut().zip(self.mean.iter()).zip(self.std.iter()) {
+                *v = (*v - mean) / std;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LossConfig {
+    #[default]
+    Mse,
+    //Smooth L1, sqrt(d^2 + epsilon^2)
+    Charbonnier { epsilon: f32 },
+}
+
+//Adam hyperparameters
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OptimizerConfig {
+    pub learning_rate: f32,
+    pub beta1: f32,
+    pub beta2: f32,
+    pub epsilon: f32,
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        Self { learning_rate: 0.001, beta1: 0.9, beta2: 0.99, epsilon: 1.0E-8 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    pub steps_per_epoch: usize,
+    //Write a checkpoint every n epochs, the last epoch is always written
+    pub checkpoint_every: usize,
+    #[serde(default)]
+    pub checkpoint_format: CheckpointFormat,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self { steps_per_epoch: 10, checkpoint_every: 1, checkpoint_format: CheckpointFormat::Native }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckpointFormat {
+    //Our own .unet files
+    #[default]
+    Native,
+    //.safetensors with the config in the metadata, readable by other tools
+    Safetensors,
+}
+
+impl CheckpointFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            CheckpointFormat::Native => "unet",
+            CheckpointFormat::Safetensors => "safetensors",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatasetConfig {
+    pub images: Vec<PathBuf>,
+    //One target per image, empty means the images are their own targets (colorization)
+    #[serde(default)]
+    pub targets: Vec<PathBuf>,
+    pub tile_size: usize,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    //Targets are label masks, geometric augmentations resample them with nearest neighbour instead of bilinear
+    #[serde(default)]
+    pub target_is_label: bool,
+    //Colorization: the images are colour photos, the input is their lightness and the target their
+    //colour in this space, so no targets are given
+    #[serde(default)]
+    pub colorize: Option<ColorSpace>,
+    //Compute per-channel input mean and std over the images before training (always done for rasters),
+    //unless model.normalization is already set
+    #[serde(default)]
+    pub normalize: bool,
+    //Threads decoding and augmenting batches in the background
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    //Number of batches the workers may prepare ahead of the training loop
+    #[serde(default = "default_prefetch")]
+    pub prefetch: usize,
+}
+
+impl DatasetConfig {
+    pub fn targets(&self) -> &[PathBuf] {
+        if self.targets.is_empty() { &self.images } else { &self.targets }
+    }
+}
+
+fn default_kernelsize() -> usize {
+    3
+}
+
+fn default_auxiliary_weight() -> f32 {
+    1.0
+}
+
+fn default_pool_size() -> usize {
+    2
+}
+
+fn default_batch_size() -> usize {
+    1
+}
+
+fn default_workers() -> usize {
+    2
+}
+
+fn default_prefetch() -> usize {
+    4
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    UnknownFormat(PathBuf),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Could not read config: {e}"),
+            ConfigError::Toml(e) => write!(f, "Invalid TOML config: {e}"),
+            ConfigError::Json(e) => write!(f, "Invalid JSON config: {e}"),
+            ConfigError::UnknownFormat(path) => write!(f, "{} is neither a .toml nor a .json file", path.display()),
+            ConfigError::Invalid(message) => write!(f, "Invalid config: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ExperimentConfig {
+    //Parses and validates a config, the format follows the file extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        let config = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&text)?,
+            Some("json") => Self::from_json(&text)?,
+            _ => return Err(ConfigError::UnknownFormat(path.to_path_buf())),
+        };
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(text).map_err(ConfigError::Toml)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = serde_json::from_str(text).map_err(ConfigError::Json)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: &str| Err(ConfigError::Invalid(message.to_string()));
+        let model = &self.model;
+        if model.inputs == 0 || model.outputs == 0 || model.width == 0 {
+            return invalid("model.inputs, model.outputs and model.width have to be positive");
+        }
+        if model.kernelsize == 0 || model.kernelsize.is_multiple_of(2) {
+            return invalid("model.kernelsize has to be odd");
+        }
+        if model.architecture == Architecture::UnetPlusPlus && (model.unet.block != Block::Plain || model.unet.attention) {
+            return invalid("model.unet.block and model.unet.attention are not supported by unet_plus_plus");
+        }
+        if model.architecture == Architecture::Fcn && model.unet.deep_supervision {
+            return invalid("model.unet.deep_supervision needs a unet or unet_plus_plus architecture");
+        }
+        if model.unet.pool_size < 2 {
+            return invalid("model.unet.pool_size has to be at least 2");
+        }
+        if model.unet.auxiliary_weight < 0.0 {
+            return invalid("model.unet.auxiliary_weight can not be negative");
+        }
+        if let Some(dropout) = &model.dropout {
+            if !(0.0 .. 1.0).contains(&dropout.rate) {
+                return invalid("model.dropout.rate has to be in [0, 1)");
+            }
+            if dropout.levels.is_empty() {
+                return invalid("model.dropout.levels must not be empty");
+            }
+            //UNets have depth + 1 levels, the FCN has as many hidden convs
+            if dropout.levels.iter().any(|&level| level > model.depth) {
+                return Err(ConfigError::Invalid(format!("model.dropout.levels have to be at most model.depth ({})", model.depth)));
+            }
+        }
+        if let Some(normalization) = &model.normalization {
+            if normalization.mean.len() != model.inputs || normalization.std.len() != model.inputs {
+                return invalid("model.normalization needs a mean and std for every input band");
+            }
+            if normalization.std.iter().any(|&std| std <= 0.0) {
+                return invalid("model.normalization.std has to be positive");
+            }
+        }
+        if let LossConfig::Charbonnier { epsilon } = self.loss {
+            if epsilon <= 0.0 {
+                return invalid("loss.epsilon has to be positive");
+            }
+        }
+        let optimizer = &self.optimizer;
+        if optimizer.learning_rate <= 0.0 || optimizer.epsilon <= 0.0 {
+            return invalid("optimizer.learning_rate and optimizer.epsilon have to be positive");
+        }
+        if !(0.0 .. 1.0).contains(&optimizer.beta1) || !(0.0 .. 1.0).contains(&optimizer.beta2) {
+            return invalid("optimizer.beta1 and optimizer.beta2 have to be in [0, 1)");
+        }
+        if self.schedule.steps_per_epoch == 0 || self.schedule.checkpoint_every == 0 {
+            return invalid("schedule.steps_per_epoch and schedule.checkpoint_every have to be positive");
+        }
+        let dataset = &self.dataset;
+        if dataset.images.is_empty() {
+            return invalid("dataset.images is empty");
+        }
+        if !dataset.targets.is_empty() && dataset.targets.len() != dataset.images.len() {
+            return invalid("dataset.targets needs exactly one target per image");
+        }
+        if dataset.tile_size == 0 || dataset.batch_size == 0 {
+            return invalid("dataset.tile_size and dataset.batch_size have to be positive");
+        }
+        if dataset.workers == 0 || dataset.prefetch == 0 {
+            return invalid("dataset.workers and dataset.prefetch have to be positive");
+        }
+        if let Some(space) = dataset.colorize {
+            if model.inputs != 1 || model.outputs != space.target_channels() || !dataset.targets.is_empty() {
+                return Err(ConfigError::Invalid(format!(
+                    "Colorizing in {space:?} needs 1 input, {} outputs and no dataset.targets", space.target_channels()
+                )));
+            }
+        }
+        for augmentation in self.augmentation.iter() {
+            augmentation.validate().map_err(ConfigError::Invalid)?;
+        }
+        if self.epochs == 0 {
+            return invalid("epochs has to be positive");
+        }
+        Ok(())
+    }
+}
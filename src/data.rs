@@ -0,0 +1,177 @@
+use crate::{checkpoint::invalid_data, color::ColorSpace, config::{ExperimentConfig, Normalization}, npy};
+use image::{DynamicImage, GrayImage, RgbImage};
+use std::{fs::File, io::{self, BufReader}, path::Path};
+use tiff::decoder::{Decoder, DecodingResult};
+
+//Loads an image as HWC floats in 0..1 with either one (luma) or three (rgb) channels
+pub fn load_image(path: impl AsRef<Path>, channels: usize) -> image::ImageResult<(Vec<f32>, usize, usize)> {
+    let image = image::open(path)?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let values = match channels {
+        1 => image.to_luma32f().to_vec(),
+        3 => image.to_rgb8()
+            .to_vec()
+            .into_iter()
+            .map(|v| v as f32 / 255.0)
+            .collect::<Vec<_>>(),
+        _ => panic!("Images can only be loaded with 1 or 3 channels, not {channels}"),
+    };
+    Ok((values, height, width))
+}
+
+//Multi-band rasters (.tif/.tiff with the bands stacked over all pages, .npy shaped [H, W] or [H, W, C])
+//keep their raw values, anything else goes through load_image
+pub fn load_input(path: impl AsRef<Path>, channels: usize) -> io::Result<(Vec<f32>, usize, usize)> {
+    let path = path.as_ref();
+    let (values, height, width, bands) = match extension(path).as_deref() {
+        Some("tif" | "tiff") => load_tiff(path)?,
+        Some("npy") => load_npy(path)?,
+        _ => {
+            let (values, height, width) = load_image(path, channels).map_err(|e| invalid_data(format!("{}: {e}", path.display())))?;
+            return Ok((values, height, width));
+        }
+    };
+    if bands != channels {
+        return Err(invalid_data(format!("{} has {bands} bands instead of {channels}", path.display())));
+    }
+    Ok((values, height, width))
+}
+
+pub fn is_raster(path: impl AsRef<Path>) -> bool {
+    matches!(extension(path.as_ref()).as_deref(), Some("tif" | "tiff" | "npy"))
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+}
+
+//Every page is one or more bands of the same size
+fn load_tiff(path: &Path) -> io::Result<(Vec<f32>, usize, usize, usize)> {
+    let tiff_error = |e: tiff::TiffError| invalid_data(format!("{}: {e}", path.display()));
+    let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(tiff_error)?;
+    let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+    let (width, height) = (width as usize, height as usize);
+    if height * width == 0 {
+        return Err(invalid_data(format!("{} is empty ({width}x{height})", path.display())));
+    }
+    let mut pages: Vec<(Vec<f32>, usize)> = Vec::new();
+    loop {
+        if decoder.dimensions().map_err(tiff_error)? != (width as u32, height as u32) {
+            return Err(invalid_data(format!("{}: page {} differs in size", path.display(), pages.len())));
+        }
+        let values: Vec<f32> = match decoder.read_image().map_err(tiff_error)? {
+            DecodingResult::U8(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::U16(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::U32(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::U64(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::F32(v) => v,
+            DecodingResult::F64(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I8(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I16(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I32(v) => v.into_iter().map(|v| v as f32).collect(),
+            DecodingResult::I64(v) => v.into_iter().map(|v| v as f32).collect(),
+        };
+        let samples = values.len() / (height * width);
+        pages.push((values, samples));
+        if !decoder.more_images() {
+            break;
+        }
+        decoder.next_image().map_err(tiff_error)?;
+    }
+    //Interleave the pages into HWC
+    let bands = pages.iter().map(|(_, samples)| samples).sum::<usize>();
+    let mut values = Vec::with_capacity(height * width * bands);
+    for pixel in 0 .. height * width {
+        for (page, samples) in pages.iter() {
+            values.extend_from_slice(&page[pixel * samples .. (pixel + 1) * samples]);
+        }
+    }
+    Ok((values, height, width, bands))
+}
+
+fn load_npy(path: &Path) -> io::Result<(Vec<f32>, usize, usize, usize)> {
+    let (shape, values) = npy::read(path)?;
+    match shape[..] {
+        [height, width] => Ok((values, height, width, 1)),
+        [height, width, bands] => Ok((values, height, width, bands)),
+        _ => Err(invalid_data(format!("{} has shape {shape:?}, expected [H, W] or [H, W, C]", path.display()))),
+    }
+}
+
+//Per-channel mean and standard deviation over all pixels of the given HWC inputs
+pub fn channel_statistics(inputs: impl IntoIterator<Item = io::Result<Vec<f32>>>, channels: usize) -> io::Result<Normalization> {
+    let mut sums = vec![0.0f64; channels];
+    let mut squares = vec![0.0f64; channels];
+    let mut count = 0;
+    for values in inputs {
+        let values = values?;
+        for pixel in values.chunks(channels) {
+            for (c, &v) in pixel.iter().enumerate() {
+                sums[c] += v as f64;
+                squares[c] += v as f64 * v as f64;
+            }
+        }
+        count += values.len() / channels;
+    }
+    if count == 0 {
+        return Err(invalid_data("No pixels to compute input statistics from".to_string()));
+    }
+    let mean = sums.iter().map(|s| s / count as f64).collect::<Vec<_>>();
+    let std = squares.iter().zip(mean.iter())
+        .map(|(s, m)| (s / count as f64 - m * m).max(0.0).sqrt())
+        //Constant channels are only shifted
+        .map(|std| if std > 1.0E-6 { std as f32 } else { 1.0 })
+        .collect();
+    Ok(Normalization { mean: mean.into_iter().map(|m| m as f32).collect(), std })
+}
+
+//What predict feeds the network for one image, normalized with the statistics stored in the config
+pub struct PredictionInput {
+    pub input: Vec<f32>,
+    pub height: usize,
+    pub width: usize,
+    //Colorization predicts the colour in this space, to be combined with the lightness before normalization
+    pub colorized: Option<(ColorSpace, Vec<f32>)>,
+}
+
+impl PredictionInput {
+    pub fn load(config: &ExperimentConfig, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (mut input, height, width, colorized) = match config.dataset.colorize {
+            Some(space) => {
+                let (rgb, height, width) = load_image(path, 3).map_err(|e| invalid_data(format!("{}: {e}", path.display())))?;
+                let (lightness, _) = space.split(&rgb);
+                (lightness.clone(), height, width, Some((space, lightness)))
+            }
+            None => {
+                let (input, height, width) = load_input(path, config.model.inputs)?;
+                (input, height, width, None)
+            }
+        };
+        if let Some(normalization) = &config.model.normalization {
+            normalization.apply(&mut input);
+        }
+        Ok(Self { input, height, width, colorized })
+    }
+}
+
+//Saves HWC floats in 0..1 with one or three channels, the format follows the file extension
+pub fn save_image(path: impl AsRef<Path>, values: &[f32], height: usize, width: usize, channels: usize) -> image::ImageResult<()> {
+    let bytes = values.iter().map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8).collect::<Vec<_>>();
+    let image = match channels {
+        1 => DynamicImage::from(GrayImage::from_vec(width as u32, height as u32, bytes).unwrap()),
+        3 => DynamicImage::from(RgbImage::from_vec(width as u32, height as u32, bytes).unwrap()),
+        _ => panic!("Images can only be saved with 1 or 3 channels, not {channels}"),
+    };
+    image.save(path)
+}
+
+//Copies a height x width window starting at (top, left) out of an HWC image
+pub fn crop(values: &[f32], width: usize, channels: usize, top: usize, left: usize, height: usize, crop_width: usize) -> Vec<f32> {
+    let mut cropped = Vec::with_capacity(height * crop_width * channels);
+    for y in top .. top + height {
+        let start = (y * width + left) * channels;
+        cropped.extend_from_slice(&values[start .. start + crop_width * channels]);
+    }
+    cropped
+}
@@ -0,0 +1,39 @@
+use crate::config::{DropoutConfig, DropoutKind};
+use descent::{module::{EvalContext, Module}, prelude::*};
+
+//Dropout that is only active in train(), test() passes the input through. The random mask comes from
+//the seed given to env.run, which training draws from the run seed. Survivors are scaled by 1 / (1 - rate)
+//so the expected activation stays the same
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dropout {
+    pub rate: f32,
+    pub kind: DropoutKind,
+}
+
+impl Dropout {
+    pub fn new(rate: f32, kind: DropoutKind) -> Self {
+        Self { rate, kind }
+    }
+
+    //The layer for the given level, if the config puts one there
+    pub fn at_level(config: &DropoutConfig, level: usize) -> Option<Self> {
+        config.levels.contains(&level).then(|| Self::new(config.rate, config.kind))
+    }
+}
+
+impl Module for Dropout {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        if !ctx.is_training || self.rate == 0.0 {
+            return input;
+        }
+        let shape = input.shape();
+        let [n, h, w, c]: [usize; 4] = shape.try_into().unwrap();
+        //Spatial dropout draws one value per sample and channel and drops the whole feature map
+        let mask_shape = match self.kind {
+            DropoutKind::Element => [n, h, w, c],
+            DropoutKind::Spatial => [n, 1, 1, c],
+        };
+        let keep = input.scope().rand(mask_shape).select_gt(self.rate, 1.0 / (1.0 - self.rate), 0.0);
+        input * keep.broadcast(shape)
+    }
+}
@@ -0,0 +1,57 @@
+use crate::{config::DropoutConfig, dropout::Dropout};
+use descent::{module::*, prelude::*};
+
+//Plain stack of conv layers without any downsampling
+pub struct FCN {
+    convs: Vec<Conv2D>,
+    //dropout[i] follows convs[i], never the output conv
+    dropout: Vec<Option<Dropout>>,
+}
+impl FCN {
+    //Builder method
+    pub fn new(env: &mut Environment, inputs: usize, outputs: usize, depth: usize, width: usize, kernelsize: usize) -> Self {
+        let mut convs = Vec::new();
+        convs.push(Conv2D::builder(inputs, width, kernelsize, kernelsize).build(env));
+
+        for _ in 0 .. depth{
+            convs.push(Conv2D::builder(width, width, kernelsize, kernelsize).build(env));
+        }
+
+        convs.push(Conv2D::builder(width, outputs, kernelsize, kernelsize).build(env));
+        let dropout = vec![None; convs.len()];
+        Self { convs, dropout }
+    }
+
+    //Adds dropout behind the hidden convs the config lists
+    pub fn with_dropout(mut self, config: &DropoutConfig) -> Self {
+        let hidden = self.convs.len() - 1;
+        for (i, dropout) in self.dropout.iter_mut().enumerate().take(hidden) {
+            *dropout = Dropout::at_level(config, i);
+        }
+        self
+    }
+
+    //Number of conv layers
+    pub fn conv_count(&self) -> usize {
+        self.convs.len()
+    }
+
+    //Names of the trainable parameters in creation order, see UNet::parameter_names
+    pub fn parameter_names(&self) -> Vec<String> {
+        (0 .. self.convs.len())
+            .flat_map(|i| [format!("convs.{i}.filter"), format!("convs.{i}.bias")])
+            .collect()
+    }
+}
+impl Module for FCN {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        let mut x = input;
+        for (conv, dropout) in self.convs.iter().zip(self.dropout.iter()){
+            x = x.apply(conv, ctx).leaky_relu(0.01);
+            if let Some(dropout) = dropout {
+                x = x.apply(dropout, ctx);
+            }
+        }
+        x
+    }
+}
\ No newline at end of file
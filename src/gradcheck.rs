@@ -0,0 +1,125 @@
+use crate::network;
+use descent::{module::*, prelude::*};
+use rand::{seq::index, Rng};
+use std::io::Write;
+
+//Finite-difference check of descent's backward passes. The expression is reduced to a scalar loss with
+//fixed random weights, every sampled element of the checked parameters is moved by +-epsilon and the
+//central difference of the loss is compared with the gradient autodiff computed for that element
+#[derive(Debug, Clone, Copy)]
+pub struct GradientCheck {
+    pub epsilon: f32,
+    //Elements checked per parameter, smaller parameters are checked completely
+    pub samples: usize,
+    //Largest accepted difference, relative for gradients above 1
+    pub tolerance: f32,
+}
+
+impl Default for GradientCheck {
+    fn default() -> Self {
+        Self { epsilon: 1e-3, samples: 16, tolerance: 1e-2 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GradientSample {
+    pub parameter: String,
+    pub index: usize,
+    pub analytic: f32,
+    pub numeric: f32,
+}
+
+impl GradientSample {
+    pub fn error(&self) -> f32 {
+        (self.analytic - self.numeric).abs() / self.analytic.abs().max(self.numeric.abs()).max(1.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GradientReport {
+    pub samples: Vec<GradientSample>,
+    pub tolerance: f32,
+}
+
+impl GradientReport {
+    pub fn failures(&self) -> Vec<&GradientSample> {
+        self.samples.iter().filter(|sample| sample.error() > self.tolerance).collect()
+    }
+
+    pub fn max_error(&self) -> f32 {
+        self.samples.iter().map(GradientSample::error).fold(0.0, f32::max)
+    }
+}
+
+impl GradientCheck {
+    //Checks the gradients of the expression with respect to every parameter in checked.
+    //expression is called for every graph that is built, so it has to build the same graph each time
+    pub fn run(&self, env: &mut Environment, checked: &[Parameter], expression: impl for<'s> Fn(&'s Scope) -> DualArray<'s>, rng: &mut impl Rng) -> GradientReport {
+        let output_shape = network::output_shape(env, &expression);
+        let weights = (0 .. output_shape.iter().product::<usize>()).map(|_| rng.gen_range(-1.0 .. 1.0)).collect::<Vec<f32>>();
+        let weight_param = env.static_parameter_with_data(output_shape, "gradcheck_weights", &weights);
+        let loss_param = env.static_parameter([1], "gradcheck_loss");
+        let grad_params = checked.iter()
+            .map(|param| env.static_parameter(param.shape(), &format!("gradcheck_{}", param.name())))
+            .collect::<Vec<_>>();
+
+        let graph = env.build_graph(|scope| {
+            //Sum over everything but the batch, like train::loss
+            let mut loss = expression(scope) * &weight_param;
+            for _ in 1 .. output_shape.len() {
+                loss = loss.reduce_sum(-1, false);
+            }
+            let loss = loss.set_loss();
+            scope.write_parameter_value(&loss_param, loss.reduce_sum(0, true));
+            for (param, grad_param) in checked.iter().zip(grad_params.iter()) {
+                scope.write_parameter_value(grad_param, scope.parameter(param).loss_grad());
+            }
+        });
+
+        env.run(&graph, 0);
+        let gradients = grad_params.iter().map(|grad_param| env.read_parameter_to_vec(grad_param)).collect::<Vec<_>>();
+
+        let mut samples = Vec::new();
+        for (param, gradient) in checked.iter().zip(gradients.iter()) {
+            let mut values = env.read_parameter_to_vec(param);
+            let indices = if values.len() <= self.samples {
+                (0 .. values.len()).collect()
+            } else {
+                index::sample(rng, values.len(), self.samples).into_vec()
+            };
+            for index in indices {
+                let original = values[index];
+                let mut loss_at = |value: f32| {
+                    values[index] = value;
+                    env.writer(param).write_all(bytemuck::cast_slice(&values)).unwrap();
+                    env.run(&graph, 0);
+                    env.read_parameter_scalar(&loss_param)
+                };
+                let numeric = (loss_at(original + self.epsilon) - loss_at(original - self.epsilon)) / (2.0 * self.epsilon);
+                loss_at(original);
+                samples.push(GradientSample { parameter: param.name(), index, analytic: gradient[index], numeric });
+            }
+        }
+        GradientReport { samples, tolerance: self.tolerance }
+    }
+
+    //Checks the gradients with respect to a random input of the given shape and every trainable parameter
+    //of the module, which are reset with rng first
+    pub fn run_module(&self, env: &mut Environment, module: &impl Module, input_shape: impl Into<Shape>, rng: &mut impl Rng) -> GradientReport {
+        let input_shape = input_shape.into();
+        let input = (0 .. input_shape.iter().product::<usize>()).map(|_| rng.gen_range(-1.0 .. 1.0)).collect::<Vec<f32>>();
+        let input_param = env.static_parameter_with_data(input_shape, "gradcheck_input", &input);
+        let parameters = {
+            let scope = env.scope();
+            module.test(scope.parameter(&input_param));
+            let parameters = scope.trainable_parameters();
+            scope.build_graph();
+            parameters
+        };
+        for param in parameters.iter() {
+            env.reset_parameter(param, rng);
+        }
+        let checked = [vec![input_param.clone()], parameters].concat();
+        self.run(env, &checked, |scope| module.test(scope.parameter(&input_param)), rng)
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{checkpoint::{invalid_data, Tensor}, config::ConfigError};
+use safetensors::{tensor::Dtype, SafeTensors};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+//How the tensor names of another framework map onto parameter_names(): the longest matching
+//prefix is replaced and a trailing .weight (PyTorch) becomes .filter, e.g.
+//  [prefixes]
+//  "enc1.conv_a." = "conv1."
+//  "bottleneck." = "inner.inner."
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NameMap {
+    #[serde(default)]
+    pub prefixes: BTreeMap<String, String>,
+}
+
+impl NameMap {
+    //TOML or JSON, following the file extension
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(ConfigError::Toml),
+            Some("json") => serde_json::from_str(&text).map_err(ConfigError::Json),
+            _ => Err(ConfigError::UnknownFormat(path.to_path_buf())),
+        }
+    }
+
+    pub fn map(&self, name: &str) -> String {
+        let renamed = self.prefixes.iter()
+            .filter(|(from, _)| name.starts_with(from.as_str()))
+            .max_by_key(|(from, _)| from.len())
+            .map_or_else(|| name.to_string(), |(from, to)| format!("{to}{}", &name[from.len() ..]));
+        match renamed.strip_suffix(".weight") {
+            Some(layer) => format!("{layer}.filter"),
+            None => renamed,
+        }
+    }
+}
+
+pub struct Import {
+    //In the order of the parameter names
+    pub tensors: Vec<Tensor>,
+    //Source tensors that did not map onto any parameter, with the name they were mapped to
+    pub unmatched: Vec<String>,
+    //Parameters no source tensor mapped onto
+    pub missing: Vec<String>,
+}
+
+//Converts every tensor of a safetensors file that maps onto one of the parameters. shapes are the
+//parameter shapes in descent's layout, OIHW conv weights are transposed to fit them
+pub fn import_safetensors(path: impl AsRef<Path>, map: &NameMap, names: &[String], shapes: &[Vec<usize>]) -> io::Result<Import> {
+    let bytes = fs::read(path)?;
+    let file = SafeTensors::deserialize(&bytes).map_err(|e| invalid_data(e.to_string()))?;
+    let mut sources = file.tensors();
+    sources.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut found: Vec<Option<Tensor>> = names.iter().map(|_| None).collect();
+    let mut unmatched = Vec::new();
+    for (source_name, view) in sources.iter() {
+        let name = map.map(source_name);
+        let Some(index) = names.iter().position(|n| *n == name).filter(|&i| found[i].is_none()) else {
+            unmatched.push(format!("{source_name} (as {name})"));
+            continue;
+        };
+        let values = to_f32(view.dtype(), view.data()).ok_or_else(|| invalid_data(format!("{source_name} has unsupported dtype {:?}", view.dtype())))?;
+        let values = to_layout(values, view.shape(), &shapes[index])
+            .ok_or_else(|| invalid_data(format!("{source_name} has shape {:?}, {name} needs {:?}", view.shape(), shapes[index])))?;
+        found[index] = Some(Tensor { name, shape: shapes[index].clone(), values });
+    }
+
+    let missing = names.iter().zip(found.iter()).filter(|(_, tensor)| tensor.is_none()).map(|(name, _)| name.clone()).collect();
+    Ok(Import { tensors: found.into_iter().flatten().collect(), unmatched, missing })
+}
+
+//Rearranges values of the source shape into the target shape. Rank 4 sources for conv filters are taken
+//as [outputs, inputs per group, kh, kw] and go to [groups, outputs per group, kh, kw, inputs per group],
+//everything else has to match up to dimensions of size 1
+fn to_layout(values: Vec<f32>, source: &[usize], target: &[usize]) -> Option<Vec<f32>> {
+    let rank = target.len();
+    let is_filter = source.len() == 4 && rank >= 4 && {
+        let [outputs, kh, kw, inputs]: [usize; 4] = target[rank - 4 ..].try_into().unwrap();
+        source == [target[.. rank - 4].iter().product::<usize>() * outputs, inputs, kh, kw]
+    };
+    if !is_filter {
+        let squeeze = |shape: &[usize]| shape.iter().copied().filter(|&d| d != 1).collect::<Vec<_>>();
+        return (squeeze(source) == squeeze(target)).then_some(values);
+    }
+    let [outputs, kh, kw, inputs]: [usize; 4] = target[rank - 4 ..].try_into().unwrap();
+    let groups = target[.. rank - 4].iter().product::<usize>();
+    let mut transposed = vec![0.0; values.len()];
+    for o in 0 .. groups * outputs {
+        for i in 0 .. inputs {
+            for y in 0 .. kh {
+                for x in 0 .. kw {
+                    transposed[((o * kh + y) * kw + x) * inputs + i] = values[((o * inputs + i) * kh + y) * kw + x];
+                }
+            }
+        }
+    }
+    Some(transposed)
+}
+
+fn to_f32(dtype: Dtype, data: &[u8]) -> Option<Vec<f32>> {
+    let values = match dtype {
+        Dtype::F32 => data.chunks_exact(4).map(|b| f32::from_le_bytes(b.try_into().unwrap())).collect(),
+        Dtype::F64 => data.chunks_exact(8).map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32).collect(),
+        Dtype::F16 => data.chunks_exact(2).map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]]))).collect(),
+        //bfloat16 is the upper half of an f32
+        Dtype::BF16 => data.chunks_exact(2).map(|b| f32::from_bits((u16::from_le_bytes([b[0], b[1]]) as u32) << 16)).collect(),
+        _ => return None,
+    };
+    Some(values)
+}
+
+pub(crate) fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as u32;
+    match exponent {
+        //Subnormal
+        0 => sign * mantissa as f32 * 2.0f32.powi(-24),
+        //Infinity and NaN
+        31 => f32::from_bits(((bits as u32 & 0x8000) << 16) | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(((bits as u32 & 0x8000) << 16) | ((exponent as u32 + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+//Rounds to the nearest f16, ties to even, too large values become infinity
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    //Drops the lowest `shift` bits of the mantissa, a carry runs over into the exponent
+    let round = |value: u32, shift: u32| {
+        let rest = value & ((1 << shift) - 1);
+        let half = 1 << (shift - 1);
+        let truncated = value >> shift;
+        truncated + (rest > half || (rest == half && truncated & 1 == 1)) as u32
+    };
+    match exponent {
+        //Infinity and NaN, NaN keeps a mantissa bit
+        143 => sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 },
+        31 .. => sign | 0x7c00,
+        //Too small even for a subnormal
+        ..= -11 => sign,
+        //Subnormal
+        -10 ..= 0 => sign | round(mantissa | 0x80_0000, (14 - exponent) as u32) as u16,
+        _ => sign | round(((exponent as u32) << 23) | mantissa, 13) as u16,
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{network::Network, seed::standard_normal};
+use descent::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FanMode {
+    FanIn,
+    FanOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Distribution {
+    Normal,
+    Uniform,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "scheme", rename_all = "snake_case", deny_unknown_fields)]
+pub enum InitScheme {
+    //Whatever descent's env.reset_parameter does
+    #[default]
+    Default,
+    //Kaiming He, std = sqrt(2 / fan)
+    He { mode: FanMode, distribution: Distribution },
+    //Glorot, std = sqrt(2 / (fan_in + fan_out))
+    Xavier { distribution: Distribution },
+    //Random orthonormal rows (or columns) of the flattened filter, scaled by gain
+    Orthogonal { gain: f32 },
+    Zero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerKind {
+    //conv1 and conv2 of every level
+    Encoder,
+    //conv3 of every level and conv4 of the inner levels
+    Decoder,
+    //The conv that produces the network output
+    Output,
+}
+
+//Init scheme per layer kind, biases of layers with a scheme other than Default start at zero
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InitConfig {
+    #[serde(default)]
+    pub encoder: InitScheme,
+    #[serde(default)]
+    pub decoder: InitScheme,
+    //Zero here makes the network start out predicting zero, e.g. for residual outputs
+    #[serde(default)]
+    pub output: InitScheme,
+}
+
+impl InitConfig {
+    pub fn scheme(&self, kind: LayerKind) -> InitScheme {
+        match kind {
+            LayerKind::Encoder => self.encoder,
+            LayerKind::Decoder => self.decoder,
+            LayerKind::Output => self.output,
+        }
+    }
+}
+
+//Overwrites freshly reset parameters according to the init config, call after env.reset_parameter
+pub fn initialize(env: &mut Environment, network: &Network, names: &[String], parameters: &[Parameter], config: &InitConfig, rng: &mut impl Rng) {
+    for (name, param) in names.iter().zip(parameters.iter()) {
+        let scheme = config.scheme(network.layer_kind(name));
+        if scheme == InitScheme::Default {
+            continue;
+        }
+        let shape = param.shape().to_vec();
+        let values = if name.ends_with(".bias") {
+            vec![0.0; shape.iter().product()]
+        } else {
+            filter_values(&shape, scheme, rng)
+        };
+        let mut writer = env.writer(param);
+        writer.write_all(bytemuck::cast_slice(&values)).unwrap();
+    }
+}
+
+//Filters are laid out as [.., outputs, kernel height, kernel width, inputs]
+pub fn filter_values(shape: &[usize], scheme: InitScheme, rng: &mut impl Rng) -> Vec<f32> {
+    let len = shape.iter().product::<usize>();
+    let [outputs, kernel_h, kernel_w, inputs]: [usize; 4] = shape[shape.len() - 4 ..].try_into().unwrap();
+    let fan_in = (inputs * kernel_h * kernel_w) as f32;
+    let fan_out = (outputs * kernel_h * kernel_w) as f32;
+    match scheme {
+        InitScheme::Default => unreachable!(),
+        InitScheme::Zero => vec![0.0; len],
+        InitScheme::He { mode, distribution } => {
+            let fan = match mode {
+                FanMode::FanIn => fan_in,
+                FanMode::FanOut => fan_out,
+            };
+            sample(len, (2.0 / fan).sqrt(), distribution, rng)
+        }
+        InitScheme::Xavier { distribution } => sample(len, (2.0 / (fan_in + fan_out)).sqrt(), distribution, rng),
+        InitScheme::Orthogonal { gain } => {
+            //Every group of output channels gets its own orthogonal matrix
+            let rows = outputs;
+            let cols = kernel_h * kernel_w * inputs;
+            (0 .. len / (rows * cols))
+                .flat_map(|_| orthogonal(rows, cols, rng))
+                .map(|v| v * gain)
+                .collect()
+        }
+    }
+}
+
+//Zero mean samples with the given standard deviation
+fn sample(len: usize, std: f32, distribution: Distribution, rng: &mut impl Rng) -> Vec<f32> {
+    match distribution {
+        Distribution::Normal => (0 .. len).map(|_| standard_normal(rng) * std).collect(),
+        Distribution::Uniform => {
+            let bound = 3.0f32.sqrt() * std;
+            (0 .. len).map(|_| rng.gen_range(-bound ..= bound)).collect()
+        }
+    }
+}
+
+//Row-major rows x cols matrix with orthonormal rows, or orthonormal columns if there are more rows than columns
+pub fn orthogonal(rows: usize, cols: usize, rng: &mut impl Rng) -> Vec<f32> {
+    let (n, m) = (rows.min(cols), rows.max(cols));
+    //Gram-Schmidt on n random vectors of length m
+    let mut vectors: Vec<Vec<f32>> = Vec::with_capacity(n);
+    while vectors.len() < n {
+        let mut v = (0 .. m).map(|_| standard_normal(rng)).collect::<Vec<_>>();
+        for u in vectors.iter() {
+            let dot = v.iter().zip(u.iter()).map(|(a, b)| a * b).sum::<f32>();
+            v.iter_mut().zip(u.iter()).for_each(|(a, b)| *a -= dot * b);
+        }
+        let norm = v.iter().map(|a| a * a).sum::<f32>().sqrt();
+        //Retry in the unlikely case the sample was (almost) linearly dependent
+        if norm > 1.0E-6 {
+            v.iter_mut().for_each(|a| *a /= norm);
+            vectors.push(v);
+        }
+    }
+    if rows <= cols {
+        vectors.concat()
+    } else {
+        (0 .. rows).flat_map(|r| vectors.iter().map(move |v| v[r]).collect::<Vec<_>>()).collect()
+    }
+}
@@ -0,0 +1,22 @@
+#![feature(int_roundings)]
+
+pub mod unet;
+pub mod unet_plus_plus;
+pub mod tiling;
+pub mod checkpoint;
+pub mod data;
+pub mod config;
+pub mod fcn;
+pub mod network;
+pub mod train;
+pub mod seed;
+pub mod init;
+pub mod augment;
+pub mod loader;
+pub mod color;
+pub mod npy;
+pub mod onnx;
+pub mod import;
+pub mod reference;
+pub mod gradcheck;
+pub mod dropout;
@@ -0,0 +1,159 @@
+use crate::{augment::{self, Augmentation, ImagePair}, color::ColorSpace, config::{DatasetConfig, ModelConfig, Normalization}, data, seed::RunSeed};
+use rand::Rng;
+use std::{panic, path::PathBuf, sync::{mpsc::{sync_channel, Receiver}, Arc, OnceLock}, thread::{self, JoinHandle}};
+
+//Ready to copy into the [batch, H, W, C] input and target parameters
+pub struct Batch {
+    pub inputs: Vec<f32>,
+    pub targets: Vec<f32>,
+}
+
+//What a worker needs to cut training batches out of the dataset
+struct BatchSpec {
+    images: Vec<PathBuf>,
+    targets: Vec<PathBuf>,
+    inputs: usize,
+    outputs: usize,
+    tile_size: usize,
+    batch_size: usize,
+    //Part of the tile the network predicts, as (top, left, height, width)
+    output_window: (usize, usize, usize, usize),
+    augmentation: Vec<Augmentation>,
+    target_is_label: bool,
+    colorize: Option<ColorSpace>,
+    normalization: Option<Normalization>,
+    seed: RunSeed,
+}
+
+//Decoded image pair with its height and width
+type Sample = (Vec<f32>, Vec<f32>, usize, usize);
+
+//Decodes, crops and augments batches on worker threads into bounded queues.
+//Batch i is produced by worker i % workers from its own seed, so the batches are the same
+//no matter how many workers there are or how they get scheduled
+pub struct Loader {
+    receivers: Vec<Receiver<Batch>>,
+    //Taken once a worker has been joined
+    workers: Vec<Option<JoinHandle<()>>>,
+    next: usize,
+}
+
+impl Loader {
+    pub fn new(dataset: &DatasetConfig, model: &ModelConfig, augmentation: &[Augmentation], output_window: (usize, usize, usize, usize), seed: RunSeed, batches: usize) -> Self {
+        let spec = Arc::new(BatchSpec {
+            images: dataset.images.clone(),
+            targets: dataset.targets().to_vec(),
+            inputs: model.inputs,
+            outputs: model.outputs,
+            tile_size: dataset.tile_size,
+            batch_size: dataset.batch_size,
+            output_window,
+            augmentation: augmentation.to_vec(),
+            target_is_label: dataset.target_is_label,
+            colorize: dataset.colorize,
+            normalization: model.normalization.clone(),
+            seed,
+        });
+        //Every image is decoded once, by whichever worker needs it first
+        let samples: Arc<Vec<OnceLock<Sample>>> = Arc::new(dataset.images.iter().map(|_| OnceLock::new()).collect());
+
+        let worker_count = dataset.workers.max(1);
+        let queue_len = dataset.prefetch.div_ceil(worker_count).max(1);
+        let (receivers, workers) = (0 .. worker_count)
+            .map(|worker| {
+                let (sender, receiver) = sync_channel(queue_len);
+                let spec = spec.clone();
+                let samples = samples.clone();
+                let handle = thread::spawn(move || {
+                    for index in (worker .. batches).step_by(worker_count) {
+                        //The receiver is gone when training stops early
+                        if sender.send(spec.batch(&samples, index)).is_err() {
+                            break;
+                        }
+                    }
+                });
+                (receiver, Some(handle))
+            })
+            .unzip();
+
+        Self { receivers, workers, next: 0 }
+    }
+}
+
+impl Iterator for Loader {
+    type Item = Batch;
+
+    fn next(&mut self) -> Option<Batch> {
+        let worker = self.next % self.receivers.len();
+        let Ok(batch) = self.receivers[worker].recv() else {
+            //A worker that hung up early panicked, its message says which file failed
+            if let Some(Err(payload)) = self.workers[worker].take().map(JoinHandle::join) {
+                panic::resume_unwind(payload);
+            }
+            return None;
+        };
+        self.next += 1;
+        Some(batch)
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        //Dropping the receivers makes the workers stop at their next send
+        self.receivers.clear();
+        for worker in self.workers.drain(..).flatten() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl BatchSpec {
+    fn sample<'a>(&self, samples: &'a [OnceLock<Sample>], index: usize) -> &'a Sample {
+        samples[index].get_or_init(|| {
+            let (image_path, target_path) = (&self.images[index], &self.targets[index]);
+            let (mut input, target, height, width) = if let Some(space) = self.colorize {
+                let (rgb, height, width) = data::load_image(image_path, 3).unwrap_or_else(|e| panic!("Could not open image: {e}"));
+                let (input, target) = space.split(&rgb);
+                (input, target, height, width)
+            } else {
+                let (input, height, width) = data::load_input(image_path, self.inputs).unwrap_or_else(|e| panic!("Could not open image: {e}"));
+                let (target, target_height, target_width) = data::load_input(target_path, self.outputs).unwrap_or_else(|e| panic!("Could not open target: {e}"));
+                assert_eq!((height, width), (target_height, target_width), "{} and {} differ in size", image_path.display(), target_path.display());
+                (input, target, height, width)
+            };
+            //Both branches end up here, batch() cuts tiles at random offsets below height - tile_size
+            assert!(height >= self.tile_size && width >= self.tile_size, "{} ({width}x{height}) is smaller than a tile", image_path.display());
+            if let Some(normalization) = &self.normalization {
+                normalization.apply(&mut input);
+            }
+            (input, target, height, width)
+        })
+    }
+
+    fn batch(&self, samples: &[OnceLock<Sample>], index: usize) -> Batch {
+        let (mut data_rng, mut augmentation_rng) = self.seed.batch_rngs(index as u64);
+        let tile = self.tile_size;
+        let (out_top, out_left, out_h, out_w) = self.output_window;
+        let mut inputs = Vec::with_capacity(self.batch_size * tile * tile * self.inputs);
+        let mut targets = Vec::with_capacity(self.batch_size * out_h * out_w * self.outputs);
+        for _ in 0 .. self.batch_size {
+            let (input, target, height, width) = self.sample(samples, data_rng.gen_range(0 .. samples.len()));
+            let top = data_rng.gen_range(0 ..= height - tile);
+            let left = data_rng.gen_range(0 ..= width - tile);
+            let mut pair = ImagePair {
+                input: data::crop(input, *width, self.inputs, top, left, tile, tile),
+                target: data::crop(target, *width, self.outputs, top, left, tile, tile),
+                height: tile,
+                width: tile,
+                input_channels: self.inputs,
+                target_channels: self.outputs,
+                target_is_label: self.target_is_label,
+            };
+            augment::apply(&self.augmentation, &mut pair, &mut augmentation_rng);
+            //The target is the part of the tile that survives the valid convolutions
+            inputs.extend(pair.input);
+            targets.extend(data::crop(&pair.target, tile, self.outputs, out_top, out_left, out_h, out_w));
+        }
+        Batch { inputs, targets }
+    }
+}
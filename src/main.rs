@@ -1,6 +1,7 @@
 #![feature(int_roundings)]
 use descent::{module::*, prelude::*, optimizer::*, module::ModuleExt};
-use rand::{thread_rng, RngCore, Rng};
+use descent_unet_example::seed::RunSeed;
+use rand::{RngCore, Rng};
 use std::{io::Write, collections::HashMap};
 
 //Unet definition, recursively holds all the conv layers
@@ -67,7 +68,10 @@ impl Module for UNet {
 
 
 fn main() {
-    let mut rng = thread_rng();
+    let seed = RunSeed::from_config(None);
+    eprintln!("Seed {}", seed.0);
+    let mut init_rng = seed.init_rng();
+    let mut rng = seed.graph_rng();
     let mut env = Environment::new();
     let unet = UNet::new(&mut env, 1,1,0,8,3);
 
@@ -120,7 +124,7 @@ fn main() {
 
     //Reset parameters and loss
     for param in parameters.iter(){
-        env.reset_parameter(param, &mut rng);
+        env.reset_parameter(param, &mut init_rng);
     }
 
     //Create some data
@@ -0,0 +1,138 @@
+use crate::{config::{Architecture, DropoutConfig, ModelConfig}, fcn::FCN, init::LayerKind, unet::UNet, unet_plus_plus::UNetPlusPlus};
+use descent::{module::*, prelude::*};
+
+//Whichever architecture the model config asks for
+pub enum Network {
+    UNet(UNet),
+    Fcn(FCN),
+    UNetPlusPlus(UNetPlusPlus),
+}
+
+impl Network {
+    pub fn new(env: &mut Environment, config: &ModelConfig) -> Self {
+        let ModelConfig { architecture, inputs, outputs, depth, width, kernelsize, unet, .. } = *config;
+        let network = match architecture {
+            Architecture::Unet => Network::UNet(UNet::with_config(env, inputs, outputs, depth, width, kernelsize, unet)),
+            Architecture::Fcn => Network::Fcn(FCN::new(env, inputs, outputs, depth, width, kernelsize)),
+            Architecture::UnetPlusPlus => {
+                Network::UNetPlusPlus(UNetPlusPlus::new(env, inputs, outputs, depth, width, kernelsize, unet))
+            }
+        };
+        match config.dropout.as_ref() {
+            Some(dropout) => network.with_dropout(dropout),
+            None => network,
+        }
+    }
+
+    pub fn with_dropout(self, config: &DropoutConfig) -> Self {
+        match self {
+            Network::UNet(unet) => Network::UNet(unet.with_dropout(config)),
+            Network::Fcn(fcn) => Network::Fcn(fcn.with_dropout(config)),
+            Network::UNetPlusPlus(unet) => Network::UNetPlusPlus(unet.with_dropout(config)),
+        }
+    }
+
+    pub fn parameter_names(&self) -> Vec<String> {
+        match self {
+            Network::UNet(unet) => unet.parameter_names(),
+            Network::Fcn(fcn) => fcn.parameter_names(),
+            Network::UNetPlusPlus(unet) => unet.parameter_names(),
+        }
+    }
+
+    //Which part of the network a parameter from parameter_names() belongs to
+    pub fn layer_kind(&self, name: &str) -> LayerKind {
+        let layer = name.rsplit_once('.').map_or(name, |(layer, _)| layer);
+        match self {
+            Network::UNet(_) => {
+                if layer == "conv4" || layer.ends_with("auxiliary") {
+                    LayerKind::Output
+                } else if ["conv1", "conv2", "encoder_shortcut", "downsample"].iter().any(|encoder| layer.ends_with(encoder)) {
+                    LayerKind::Encoder
+                } else {
+                    LayerKind::Decoder
+                }
+            }
+            Network::Fcn(fcn) => {
+                if layer == format!("convs.{}", fcn.conv_count() - 1) { LayerKind::Output } else { LayerKind::Encoder }
+            }
+            Network::UNetPlusPlus(_) => {
+                if layer.starts_with("head") {
+                    LayerKind::Output
+                } else if layer.starts_with("down") || layer.split_once('.').is_some_and(|(node, _)| node.ends_with("_0")) {
+                    LayerKind::Encoder
+                } else {
+                    LayerKind::Decoder
+                }
+            }
+        }
+    }
+
+    //Number of outputs eval_outputs returns, more than one with deep supervision
+    pub fn output_count(&self) -> usize {
+        match self {
+            Network::UNet(unet) => unet.output_count(),
+            Network::UNetPlusPlus(unet) => unet.output_count(),
+            Network::Fcn(_) => 1,
+        }
+    }
+
+    //The output followed by the deep supervision outputs, all of the same size
+    pub fn eval_outputs<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> Vec<DualArray<'s>> {
+        match self {
+            Network::UNet(unet) => unet.eval_outputs(input, ctx),
+            Network::UNetPlusPlus(unet) => unet.eval_outputs(input, ctx),
+            Network::Fcn(fcn) => vec![fcn.eval(input, ctx)],
+        }
+    }
+
+    //Trainable parameters in the same order as parameter_names(), found by building a throwaway graph
+    pub fn parameters(&self, env: &mut Environment, input_shape: impl Into<Shape>) -> Vec<Parameter> {
+        let input_param = env.static_parameter(input_shape, "parameters_input");
+        let scope = env.scope();
+        self.test(scope.parameter(&input_param));
+        let parameters = scope.trainable_parameters();
+        scope.build_graph();
+        parameters
+    }
+}
+
+impl Module for Network {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        match self {
+            Network::UNet(unet) => unet.eval(input, ctx),
+            Network::Fcn(fcn) => fcn.eval(input, ctx),
+            Network::UNetPlusPlus(unet) => unet.eval(input, ctx),
+        }
+    }
+}
+
+//The outputs of Network::eval_outputs stacked along the channels, so that every deep supervision head
+//can be gradient checked as one module
+pub struct DeepSupervision<'a>(pub &'a Network);
+
+impl Module for DeepSupervision<'_> {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        self.0.eval_outputs(input, ctx).into_iter().reduce(|stacked, output| stacked.concat(output, -1)).unwrap()
+    }
+}
+
+//Shape of whatever expression builds, found by building a throwaway graph
+pub fn output_shape(env: &Environment, expression: impl for<'s> Fn(&'s Scope) -> DualArray<'s>) -> Shape {
+    let scope = env.scope();
+    let y = expression(&scope);
+    env.scope().build_graph();
+    y.shape()
+}
+
+//Runs f on the input parameter as a graph of its own and reads back the output and its shape
+pub fn run_once(env: &mut Environment, input: &Parameter, f: impl for<'s> Fn(DualArray<'s>) -> DualArray<'s>, seed: u32) -> (Vec<f32>, Shape) {
+    let shape = output_shape(env, |scope| f(scope.parameter(input)));
+    let output = env.static_parameter(shape, "run_once_output");
+    let graph = env.build_graph(|scope| {
+        let y = f(scope.parameter(input));
+        scope.write_parameter_value(&output, y.value());
+    });
+    env.run(&graph, seed);
+    (env.read_parameter_to_vec(&output), shape)
+}
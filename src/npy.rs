@@ -0,0 +1,167 @@
+use crate::{checkpoint::{invalid_data, Tensor}, import::{f16_to_f32, f32_to_f16}};
+use std::{fs::File, io::{self, BufReader, BufWriter, Read, Write}, path::Path};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+//Element types arrays can be written as, values are rounded to nearest, and clamped for the integer types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dtype {
+    F32,
+    F16,
+    U8,
+    I64,
+}
+
+impl Dtype {
+    fn descr(self) -> &'static str {
+        match self {
+            Dtype::F32 => "<f4",
+            Dtype::F16 => "<f2",
+            Dtype::U8 => "|u1",
+            Dtype::I64 => "<i8",
+        }
+    }
+}
+
+//Reads a little endian, C order .npy array of any integer or float type as floats, returns shape and values
+pub fn read(path: impl AsRef<Path>) -> io::Result<(Vec<usize>, Vec<f32>)> {
+    read_from(&mut BufReader::new(File::open(path)?))
+}
+
+pub fn read_from(reader: &mut impl Read) -> io::Result<(Vec<usize>, Vec<f32>)> {
+    let mut preamble = [0u8; 8];
+    reader.read_exact(&mut preamble)?;
+    if &preamble[.. 6] != MAGIC {
+        return Err(invalid_data("Not a .npy file".to_string()));
+    }
+    //Version 1 has a 16 bit header length, versions 2 and 3 a 32 bit one
+    let header_len = if preamble[6] == 1 {
+        let mut bytes = [0u8; 2];
+        reader.read_exact(&mut bytes)?;
+        u16::from_le_bytes(bytes) as usize
+    } else {
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        u32::from_le_bytes(bytes) as usize
+    };
+    let header = read_bytes(reader, header_len, "header")?;
+    let header = String::from_utf8(header).map_err(|_| invalid_data("Header is not valid utf-8".to_string()))?;
+    let (descr, shape) = parse_header(&header)?;
+
+    let size = descr.get(2 ..).and_then(|size| size.parse::<usize>().ok()).ok_or_else(|| invalid_data(format!("Unsupported dtype {descr}")))?;
+    let len = shape.iter().try_fold(size, |len, dim| len.checked_mul(*dim))
+        .ok_or_else(|| invalid_data(format!("Shape {shape:?} is too large")))?;
+    let bytes = read_bytes(reader, len, "data")?;
+    let values = match &descr[1 ..] {
+        "f2" => convert(&bytes, |b| f16_to_f32(u16::from_le_bytes(b))),
+        "f4" => convert(&bytes, f32::from_le_bytes),
+        "f8" => convert(&bytes, |b| f64::from_le_bytes(b) as f32),
+        "u1" => convert(&bytes, |b: [u8; 1]| b[0] as f32),
+        "u2" => convert(&bytes, |b| u16::from_le_bytes(b) as f32),
+        "u4" => convert(&bytes, |b| u32::from_le_bytes(b) as f32),
+        "i1" => convert(&bytes, |b: [u8; 1]| b[0] as i8 as f32),
+        "i2" => convert(&bytes, |b| i16::from_le_bytes(b) as f32),
+        "i4" => convert(&bytes, |b| i32::from_le_bytes(b) as f32),
+        "i8" => convert(&bytes, |b| i64::from_le_bytes(b) as f32),
+        _ => return Err(invalid_data(format!("Unsupported dtype {descr}"))),
+    };
+    Ok((shape, values))
+}
+
+//Lengths come from the file, so the buffer only grows as far as the data really goes
+fn read_bytes(reader: &mut impl Read, len: usize, what: &str) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(invalid_data(format!("File is truncated, {what} has {} of {len} bytes", bytes.len())));
+    }
+    Ok(bytes)
+}
+
+//Pulls dtype and shape out of a header like {'descr': '<f4', 'fortran_order': False, 'shape': (3, 4), }
+fn parse_header(header: &str) -> io::Result<(String, Vec<usize>)> {
+    let value = |key: &str| {
+        let start = header.find(&format!("'{key}':")).ok_or_else(|| invalid_data(format!("Header has no {key}")))?;
+        Ok::<_, io::Error>(header[start + key.len() + 3 ..].trim_start())
+    };
+    let descr = value("descr")?.trim_start_matches('\'').split('\'').next().unwrap_or_default().to_string();
+    if !(descr.starts_with('<') || descr.starts_with('|')) || descr.len() < 3 {
+        return Err(invalid_data(format!("Only little endian arrays are supported, not {descr}")));
+    }
+    if value("fortran_order")?.starts_with("True") {
+        return Err(invalid_data("Only C order arrays are supported".to_string()));
+    }
+    let (shape, _) = value("shape")?
+        .strip_prefix('(')
+        .and_then(|shape| shape.split_once(')'))
+        .ok_or_else(|| invalid_data("Malformed shape".to_string()))?;
+    let shape = shape
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>().map_err(|_| invalid_data(format!("Malformed shape dimension {dim}"))))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok((descr, shape))
+}
+
+//Version 1 .npy file in C order
+pub fn write(path: impl AsRef<Path>, shape: &[usize], values: &[f32], dtype: Dtype) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_to(&mut writer, shape, values, dtype)?;
+    writer.flush()
+}
+
+pub fn write_to(writer: &mut impl Write, shape: &[usize], values: &[f32], dtype: Dtype) -> io::Result<()> {
+    assert_eq!(shape.iter().product::<usize>(), values.len(), "Shape {shape:?} does not match {} values", values.len());
+    //Python tuple syntax, so a single dimension needs a trailing comma
+    let dims = match shape {
+        [dim] => format!("{dim},"),
+        _ => shape.iter().map(|dim| dim.to_string()).collect::<Vec<_>>().join(", "),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': ({dims}), }}", dtype.descr());
+    //The data starts at a multiple of 64 bytes and the header ends with a newline
+    let padding = (64 - (MAGIC.len() + 4 + header.len() + 1) % 64) % 64;
+    header += &" ".repeat(padding);
+    header.push('\n');
+    let header_len = u16::try_from(header.len()).map_err(|_| invalid_data(format!("Shape {shape:?} has too many dimensions")))?;
+
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[1, 0])?;
+    writer.write_all(&header_len.to_le_bytes())?;
+    writer.write_all(header.as_bytes())?;
+    let bytes: Vec<u8> = match dtype {
+        Dtype::F32 => values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        Dtype::F16 => values.iter().flat_map(|v| f32_to_f16(*v).to_le_bytes()).collect(),
+        Dtype::U8 => values.iter().map(|v| v.round().clamp(0.0, 255.0) as u8).collect(),
+        Dtype::I64 => values.iter().flat_map(|v| (v.round() as i64).to_le_bytes()).collect(),
+    };
+    writer.write_all(&bytes)
+}
+
+//Every array of an .npz archive (numpy's savez and savez_compressed), named without the .npy extension
+pub fn read_npz(path: impl AsRef<Path>) -> io::Result<Vec<Tensor>> {
+    let mut archive = ZipArchive::new(BufReader::new(File::open(path)?))?;
+    (0 .. archive.len())
+        .map(|index| {
+            let mut file = archive.by_index(index)?;
+            let name = file.name().trim_end_matches(".npy").to_string();
+            let (shape, values) = read_from(&mut file)?;
+            Ok(Tensor { name, shape, values })
+        })
+        .collect()
+}
+
+//Uncompressed .npz archive like numpy's savez
+pub fn write_npz(path: impl AsRef<Path>, tensors: &[Tensor], dtype: Dtype) -> io::Result<()> {
+    let mut archive = ZipWriter::new(BufWriter::new(File::create(path)?));
+    for tensor in tensors.iter() {
+        archive.start_file(format!("{}.npy", tensor.name), FileOptions::default().compression_method(CompressionMethod::Stored))?;
+        write_to(&mut archive, &tensor.shape, &tensor.values, dtype)?;
+    }
+    archive.finish()?.flush()
+}
+
+fn convert<const N: usize>(bytes: &[u8], f: impl Fn([u8; N]) -> f32) -> Vec<f32> {
+    bytes.chunks_exact(N).map(|b| f(b.try_into().unwrap())).collect()
+}
@@ -0,0 +1,423 @@
+use crate::{checkpoint::{invalid_data, Checkpoint}, config::{Architecture, Block, Downsampling}, unet::SkipAlignment};
+use prost::Message;
+use std::{fs, io, path::Path};
+
+//The part of the ONNX schema (onnx.proto3) the export needs, with the field numbers of the spec.
+//Oneof fields are declared as plain optional fields, which is the same on the wire
+#[derive(Clone, PartialEq, Message)]
+pub struct ModelProto {
+    #[prost(int64, tag = "1")]
+    pub ir_version: i64,
+    #[prost(string, tag = "2")]
+    pub producer_name: String,
+    #[prost(message, optional, tag = "7")]
+    pub graph: Option<GraphProto>,
+    #[prost(message, repeated, tag = "8")]
+    pub opset_import: Vec<OperatorSetIdProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct OperatorSetIdProto {
+    #[prost(string, tag = "1")]
+    pub domain: String,
+    #[prost(int64, tag = "2")]
+    pub version: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct GraphProto {
+    #[prost(message, repeated, tag = "1")]
+    pub node: Vec<NodeProto>,
+    #[prost(string, tag = "2")]
+    pub name: String,
+    #[prost(message, repeated, tag = "5")]
+    pub initializer: Vec<TensorProto>,
+    #[prost(message, repeated, tag = "11")]
+    pub input: Vec<ValueInfoProto>,
+    #[prost(message, repeated, tag = "12")]
+    pub output: Vec<ValueInfoProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct NodeProto {
+    #[prost(string, repeated, tag = "1")]
+    pub input: Vec<String>,
+    #[prost(string, repeated, tag = "2")]
+    pub output: Vec<String>,
+    #[prost(string, tag = "3")]
+    pub name: String,
+    #[prost(string, tag = "4")]
+    pub op_type: String,
+    #[prost(message, repeated, tag = "5")]
+    pub attribute: Vec<AttributeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct AttributeProto {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(float, tag = "2")]
+    pub f: f32,
+    #[prost(int64, tag = "3")]
+    pub i: i64,
+    #[prost(bytes = "vec", tag = "4")]
+    pub s: Vec<u8>,
+    #[prost(int64, repeated, tag = "8")]
+    pub ints: Vec<i64>,
+    #[prost(int32, tag = "20")]
+    pub r#type: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TensorProto {
+    #[prost(int64, repeated, tag = "1")]
+    pub dims: Vec<i64>,
+    #[prost(int32, tag = "2")]
+    pub data_type: i32,
+    #[prost(float, repeated, tag = "4")]
+    pub float_data: Vec<f32>,
+    #[prost(int64, repeated, tag = "7")]
+    pub int64_data: Vec<i64>,
+    #[prost(string, tag = "8")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ValueInfoProto {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(message, optional, tag = "2")]
+    pub r#type: Option<TypeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TypeProto {
+    #[prost(message, optional, tag = "1")]
+    pub tensor_type: Option<TensorTypeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TensorTypeProto {
+    #[prost(int32, tag = "1")]
+    pub elem_type: i32,
+    #[prost(message, optional, tag = "2")]
+    pub shape: Option<TensorShapeProto>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TensorShapeProto {
+    #[prost(message, repeated, tag = "1")]
+    pub dim: Vec<Dimension>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Dimension {
+    #[prost(int64, optional, tag = "1")]
+    pub dim_value: Option<i64>,
+    #[prost(string, optional, tag = "2")]
+    pub dim_param: Option<String>,
+}
+
+//TensorProto.DataType and AttributeProto.AttributeType values
+pub const FLOAT: i32 = 1;
+pub const INT64: i32 = 7;
+const ATTRIBUTE_FLOAT: i32 = 1;
+const ATTRIBUTE_INT: i32 = 2;
+const ATTRIBUTE_STRING: i32 = 3;
+const ATTRIBUTE_INTS: i32 = 7;
+
+const OPSET: i64 = 13;
+
+//Builds an ONNX model of the checkpoint for tiles of the given size. ONNX convolutions are NCHW,
+//so the model takes [batch, C, H, W] inputs instead of descent's [batch, H, W, C]. The input
+//normalization of the model config is part of the graph
+pub fn export(checkpoint: &Checkpoint, tile_height: usize, tile_width: usize) -> io::Result<ModelProto> {
+    let model = &checkpoint.config.model;
+    let mut graph = GraphBuilder { checkpoint, nodes: Vec::new(), initializers: Vec::new() };
+    let mut x = "input".to_string();
+    let mut shape = (model.inputs, tile_height, tile_width);
+    if let Some(normalization) = &model.normalization {
+        let channel_shape = [1, model.inputs, 1, 1];
+        let mean = graph.initializer("normalization.mean", &channel_shape, normalization.mean.clone());
+        let std = graph.initializer("normalization.std", &channel_shape, normalization.std.clone());
+        x = graph.node("Sub", vec![x, mean], Vec::new());
+        x = graph.node("Div", vec![x, std], Vec::new());
+    }
+    match model.architecture {
+        Architecture::Unet => {
+            graph.unet(x, "", model.depth, &mut shape)?;
+        }
+        Architecture::Fcn => {
+            for i in 0 .. model.depth + 2 {
+                x = graph.conv(x, &format!("convs.{i}"), &mut shape)?;
+            }
+        }
+        Architecture::UnetPlusPlus => {
+            graph.unet_plus_plus(x, model.depth, &mut shape)?;
+        }
+    }
+    //The last node produces the network output
+    graph.nodes.last_mut().unwrap().output = vec!["output".to_string()];
+
+    let value_info = |name: &str, (channels, height, width): (usize, usize, usize)| ValueInfoProto {
+        name: name.to_string(),
+        r#type: Some(TypeProto {
+            tensor_type: Some(TensorTypeProto {
+                elem_type: FLOAT,
+                shape: Some(TensorShapeProto {
+                    dim: vec![
+                        Dimension { dim_value: None, dim_param: Some("batch".to_string()) },
+                        Dimension { dim_value: Some(channels as i64), dim_param: None },
+                        Dimension { dim_value: Some(height as i64), dim_param: None },
+                        Dimension { dim_value: Some(width as i64), dim_param: None },
+                    ],
+                }),
+            }),
+        }),
+    };
+    Ok(ModelProto {
+        ir_version: 7,
+        producer_name: env!("CARGO_PKG_NAME").to_string(),
+        graph: Some(GraphProto {
+            node: graph.nodes,
+            name: format!("{:?}", model.architecture).to_lowercase(),
+            initializer: graph.initializers,
+            input: vec![value_info("input", (model.inputs, tile_height, tile_width))],
+            output: vec![value_info("output", shape)],
+        }),
+        opset_import: vec![OperatorSetIdProto { domain: String::new(), version: OPSET }],
+    })
+}
+
+pub fn save(model: &ModelProto, path: impl AsRef<Path>) -> io::Result<()> {
+    fs::write(path, model.encode_to_vec())
+}
+
+pub fn load(path: impl AsRef<Path>) -> io::Result<ModelProto> {
+    ModelProto::decode(fs::read(path)?.as_slice()).map_err(|e| invalid_data(e.to_string()))
+}
+
+struct GraphBuilder<'a> {
+    checkpoint: &'a Checkpoint,
+    nodes: Vec<NodeProto>,
+    initializers: Vec<TensorProto>,
+}
+
+impl GraphBuilder<'_> {
+    //Adds a node with a single output named after the node and returns that name
+    fn node(&mut self, op_type: &str, input: Vec<String>, attribute: Vec<AttributeProto>) -> String {
+        let name = format!("{op_type}_{}", self.nodes.len());
+        self.nodes.push(NodeProto { input, output: vec![name.clone()], name: name.clone(), op_type: op_type.to_string(), attribute });
+        name
+    }
+
+    fn initializer(&mut self, name: &str, dims: &[usize], float_data: Vec<f32>) -> String {
+        self.initializers.push(TensorProto {
+            dims: dims.iter().map(|&d| d as i64).collect(),
+            data_type: FLOAT,
+            float_data,
+            name: name.to_string(),
+            ..Default::default()
+        });
+        name.to_string()
+    }
+
+    fn int64_initializer(&mut self, name: String, int64_data: Vec<i64>) -> String {
+        self.initializers.push(TensorProto {
+            dims: vec![int64_data.len() as i64],
+            data_type: INT64,
+            int64_data,
+            name: name.clone(),
+            ..Default::default()
+        });
+        name
+    }
+
+    //Valid conv followed by the leaky relu, shape is (channels, height, width)
+    fn conv(&mut self, x: String, layer: &str, shape: &mut (usize, usize, usize)) -> io::Result<String> {
+        let x = self.linear_conv(x, layer, shape)?;
+        Ok(self.node("LeakyRelu", vec![x], vec![float("alpha", 0.01)]))
+    }
+
+    fn linear_conv(&mut self, x: String, layer: &str, shape: &mut (usize, usize, usize)) -> io::Result<String> {
+        self.strided_conv(x, layer, 1, shape)
+    }
+
+    fn strided_conv(&mut self, x: String, layer: &str, stride: usize, shape: &mut (usize, usize, usize)) -> io::Result<String> {
+        let tensor = |name: String| self.checkpoint.get(&name).ok_or_else(|| invalid_data(format!("Parameter {name} not found in checkpoint")));
+        let filter = tensor(format!("{layer}.filter"))?;
+        let bias = tensor(format!("{layer}.bias"))?;
+        //descent filters are [groups, outputs per group, kh, kw, inputs per group], ONNX wants [outputs, inputs per group, kh, kw]
+        let rank = filter.shape.len();
+        let [outputs, kh, kw, inputs]: [usize; 4] = filter.shape[rank - 4 ..].try_into().unwrap();
+        let groups = filter.shape[.. rank - 4].iter().product::<usize>();
+        if inputs * groups != shape.0 {
+            return Err(invalid_data(format!("{layer} expects {} inputs, got {}", inputs * groups, shape.0)));
+        }
+        let mut weights = vec![0.0; filter.values.len()];
+        for o in 0 .. groups * outputs {
+            for y in 0 .. kh {
+                for x in 0 .. kw {
+                    for i in 0 .. inputs {
+                        weights[((o * inputs + i) * kh + y) * kw + x] = filter.values[((o * kh + y) * kw + x) * inputs + i];
+                    }
+                }
+            }
+        }
+        let bias_values = bias.values.clone();
+        let weight = self.initializer(&format!("{layer}.filter"), &[groups * outputs, inputs, kh, kw], weights);
+        let bias = self.initializer(&format!("{layer}.bias"), &[groups * outputs], bias_values);
+        *shape = (groups * outputs, (shape.1 - kh) / stride + 1, (shape.2 - kw) / stride + 1);
+        Ok(self.node("Conv", vec![x, weight, bias], vec![
+            ints("kernel_shape", &[kh, kw]),
+            ints("strides", &[stride, stride]),
+            int("group", groups as i64),
+        ]))
+    }
+
+    //Mirrors unet::block
+    fn block(&mut self, x: String, first: &str, second: &str, shortcut: Option<String>, shape: &mut (usize, usize, usize)) -> io::Result<String> {
+        let (c_in, h_in, w_in) = *shape;
+        let y = self.conv(x.clone(), first, shape)?;
+        let y = self.linear_conv(y, second, shape)?;
+        let y = match shortcut {
+            Some(shortcut) => {
+                let (_, h_out, w_out) = *shape;
+                let cropped = self.center_crop(x, &shortcut, (h_in, w_in), (h_out, w_out));
+                let mut shortcut_shape = (c_in, h_out, w_out);
+                let projected = self.linear_conv(cropped, &shortcut, &mut shortcut_shape)?;
+                self.node("Add", vec![y, projected], Vec::new())
+            }
+            None => y,
+        };
+        Ok(self.node("LeakyRelu", vec![y], vec![float("alpha", 0.01)]))
+    }
+
+    //Mirrors UNet::eval
+    fn unet(&mut self, x: String, prefix: &str, depth: usize, shape: &mut (usize, usize, usize)) -> io::Result<String> {
+        let residual = self.checkpoint.config.model.unet.block == Block::Residual;
+        let shortcut = |name: &str| residual.then(|| format!("{prefix}{name}"));
+        let x = self.block(x, &format!("{prefix}conv1"), &format!("{prefix}conv2"), shortcut("encoder_shortcut"), shape)?;
+        let x = if depth > 0 {
+            let (channels, h_outer, w_outer) = *shape;
+            let mut inner_shape = *shape;
+            let pooled = self.downsample(x.clone(), &format!("{prefix}downsample"), &mut inner_shape)?;
+            let inner = self.unet(pooled, &format!("{prefix}inner."), depth - 1, &mut inner_shape)?;
+            let (inner_channels, h_inner, w_inner) = inner_shape;
+
+            let cropped = self.align(inner, prefix, (h_outer, w_outer), (h_inner, w_inner));
+
+            //Attention gate, see unet::AttentionGate
+            let x = if self.checkpoint.config.model.unet.attention {
+                let mut q_shape = (channels, h_outer, w_outer);
+                let skip = self.linear_conv(x.clone(), &format!("{prefix}attention.skip"), &mut q_shape)?;
+                let gate = self.linear_conv(cropped.clone(), &format!("{prefix}attention.gate"), &mut (inner_channels, h_outer, w_outer))?;
+                let q = self.node("Add", vec![skip, gate], Vec::new());
+                let q = self.node("LeakyRelu", vec![q], vec![float("alpha", 0.01)]);
+                let map = self.linear_conv(q, &format!("{prefix}attention.psi"), &mut q_shape)?;
+                let map = self.node("Sigmoid", vec![map], Vec::new());
+                self.node("Mul", vec![x, map], Vec::new())
+            } else {
+                x
+            };
+
+            *shape = (channels + inner_channels, h_outer, w_outer);
+            self.node("Concat", vec![x, cropped], vec![int("axis", 1)])
+        } else {
+            x
+        };
+        self.block(x, &format!("{prefix}conv3"), &format!("{prefix}conv4"), shortcut("decoder_shortcut"), shape)
+    }
+
+    //Mirrors UNetPlusPlus::eval, x[i][j] is node (i, j) with its (channels, height, width)
+    fn unet_plus_plus(&mut self, input: String, depth: usize, shape: &mut (usize, usize, usize)) -> io::Result<String> {
+        let node = |graph: &mut Self, x: String, i: usize, j: usize, shape: &mut (usize, usize, usize)| {
+            graph.block(x, &format!("node{i}_{j}.conv1"), &format!("node{i}_{j}.conv2"), None, shape)
+        };
+        let mut x = Vec::with_capacity(depth + 1);
+        let top = node(self, input, 0, 0, shape)?;
+        x.push(vec![(top, *shape)]);
+        for i in 1 ..= depth {
+            let (previous, mut shape) = x[i - 1][0].clone();
+            let pooled = self.downsample(previous, &format!("down{i}"), &mut shape)?;
+            let y = node(self, pooled, i, 0, &mut shape)?;
+            x.push(vec![(y, shape)]);
+        }
+        for j in 1 ..= depth {
+            for i in 0 ..= depth - j {
+                let (_, height, width) = x[i][j - 1].1;
+                let (below, (below_channels, h_below, w_below)) = x[i + 1][j - 1].clone();
+                let upsampled = self.align(below, &format!("node{i}_{j}."), (height, width), (h_below, w_below));
+                let mut inputs = Vec::new();
+                let mut channels = 0;
+                for (k, (earlier, (c, h, w))) in x[i].clone().into_iter().enumerate() {
+                    inputs.push(if (h, w) == (height, width) { earlier } else { self.center_crop(earlier, &format!("node{i}_{j}.skip{k}"), (h, w), (height, width)) });
+                    channels += c;
+                }
+                inputs.push(upsampled);
+                let concatenated = self.node("Concat", inputs, vec![int("axis", 1)]);
+                let mut shape = (channels + below_channels, height, width);
+                let y = node(self, concatenated, i, j, &mut shape)?;
+                x[i].push((y, shape));
+            }
+        }
+        let (top, top_shape) = x[0][depth].clone();
+        *shape = top_shape;
+        self.conv(top, &format!("head{depth}"), shape)
+    }
+
+    //Mirrors unet::Downsample, layer is only used by strided convs. ONNX pooling rounds down by default like descent
+    fn downsample(&mut self, x: String, layer: &str, shape: &mut (usize, usize, usize)) -> io::Result<String> {
+        let size = self.checkpoint.config.model.unet.pool_size;
+        let op_type = match self.checkpoint.config.model.unet.downsampling {
+            Downsampling::Max => "MaxPool",
+            Downsampling::Average => "AveragePool",
+            Downsampling::StridedConv => {
+                let y = self.strided_conv(x, layer, size, shape)?;
+                return Ok(self.node("LeakyRelu", vec![y], vec![float("alpha", 0.01)]));
+            }
+        };
+        *shape = (shape.0, shape.1 / size, shape.2 / size);
+        Ok(self.node(op_type, vec![x], vec![ints("kernel_shape", &[size, size]), ints("strides", &[size, size])]))
+    }
+
+    //Nearest neighbour upsampling of an inner (height, width) by whole factors, then a centered crop to the outer size
+    fn align(&mut self, x: String, prefix: &str, outer: (usize, usize), inner: (usize, usize)) -> String {
+        let skip = SkipAlignment::new(outer, inner);
+        let scales = self.initializer(&format!("{prefix}upsample.scales"), &[4], vec![1.0, 1.0, skip.factor_y as f32, skip.factor_x as f32]);
+        let upsampled = self.node("Resize", vec![x, String::new(), scales], vec![
+            string("mode", "nearest"),
+            string("coordinate_transformation_mode", "asymmetric"),
+            string("nearest_mode", "floor"),
+        ]);
+        let starts = self.int64_initializer(format!("{prefix}crop.starts"), vec![skip.top as i64, skip.left as i64]);
+        let ends = self.int64_initializer(format!("{prefix}crop.ends"), vec![(skip.top + outer.0) as i64, (skip.left + outer.1) as i64]);
+        let axes = self.int64_initializer(format!("{prefix}crop.axes"), vec![2, 3]);
+        self.node("Slice", vec![upsampled, starts, ends, axes], Vec::new())
+    }
+
+    //Slice of the centered (height, width) window
+    fn center_crop(&mut self, x: String, name: &str, (h_in, w_in): (usize, usize), (h_out, w_out): (usize, usize)) -> String {
+        let (top, left) = ((h_in - h_out) / 2, (w_in - w_out) / 2);
+        let starts = self.int64_initializer(format!("{name}.crop.starts"), vec![top as i64, left as i64]);
+        let ends = self.int64_initializer(format!("{name}.crop.ends"), vec![(top + h_out) as i64, (left + w_out) as i64]);
+        let axes = self.int64_initializer(format!("{name}.crop.axes"), vec![2, 3]);
+        self.node("Slice", vec![x, starts, ends, axes], Vec::new())
+    }
+}
+
+fn float(name: &str, f: f32) -> AttributeProto {
+    AttributeProto { name: name.to_string(), f, r#type: ATTRIBUTE_FLOAT, ..Default::default() }
+}
+
+fn int(name: &str, i: i64) -> AttributeProto {
+    AttributeProto { name: name.to_string(), i, r#type: ATTRIBUTE_INT, ..Default::default() }
+}
+
+fn ints(name: &str, ints: &[usize]) -> AttributeProto {
+    AttributeProto { name: name.to_string(), ints: ints.iter().map(|&i| i as i64).collect(), r#type: ATTRIBUTE_INTS, ..Default::default() }
+}
+
+fn string(name: &str, s: &str) -> AttributeProto {
+    AttributeProto { name: name.to_string(), s: s.as_bytes().to_vec(), r#type: ATTRIBUTE_STRING, ..Default::default() }
+}
@@ -0,0 +1,266 @@
+use crate::{checkpoint::{invalid_data, Checkpoint, Tensor}, config::{Architecture, Block, Downsampling}, tiling, unet::SkipAlignment};
+use std::io;
+
+//Plain Rust forward pass of a checkpoint, the same ops as UNet::eval, UNetPlusPlus::eval and FCN::eval without descent.
+//Slow, but simple enough to check the GPU results against and it runs where there is no GPU
+
+//A single HWC feature map
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureMap {
+    pub values: Vec<f32>,
+    pub height: usize,
+    pub width: usize,
+    pub channels: usize,
+}
+
+impl FeatureMap {
+    pub fn new(values: Vec<f32>, height: usize, width: usize, channels: usize) -> Self {
+        assert_eq!(values.len(), height * width * channels);
+        Self { values, height, width, channels }
+    }
+
+    fn at(&self, y: usize, x: usize) -> &[f32] {
+        let start = (y * self.width + x) * self.channels;
+        &self.values[start .. start + self.channels]
+    }
+}
+
+//Runs the network of the checkpoint on one (already normalized) input
+pub fn forward(checkpoint: &Checkpoint, input: FeatureMap) -> io::Result<FeatureMap> {
+    forward_with_attention(checkpoint, input).map(|(y, _)| y)
+}
+
+//forward that also returns the attention maps like UNet::eval_with_attention
+pub fn forward_with_attention(checkpoint: &Checkpoint, input: FeatureMap) -> io::Result<(FeatureMap, Vec<FeatureMap>)> {
+    let model = &checkpoint.config.model;
+    match model.architecture {
+        Architecture::Unet => unet(checkpoint, input, "", model.depth),
+        Architecture::Fcn => {
+            let y = (0 .. model.depth + 2).try_fold(input, |x, i| Ok::<_, io::Error>(leaky_relu(conv_layer(checkpoint, &x, &format!("convs.{i}"))?, 0.01)))?;
+            Ok((y, Vec::new()))
+        }
+        Architecture::UnetPlusPlus => Ok((unet_plus_plus(checkpoint, input, model.depth)?, Vec::new())),
+    }
+}
+
+//Overlap-tile prediction of a whole HWC image like TiledInference::run, one tile at a time.
+//With attention_maps the maps follow the predicted channels like unet::WithAttentionMaps
+pub fn predict(checkpoint: &Checkpoint, image: &[f32], height: usize, width: usize, tile_size: usize, attention_maps: bool) -> io::Result<Vec<f32>> {
+    let inputs = checkpoint.config.model.inputs;
+    let tile_len = tile_size * tile_size * inputs;
+    let run = |values: Vec<f32>| {
+        let (y, maps) = forward_with_attention(checkpoint, FeatureMap::new(values, tile_size, tile_size, inputs))?;
+        Ok::<_, io::Error>(if attention_maps { maps.iter().fold(y, |y, map| concat(&y, map)) } else { y })
+    };
+    //A zero tile gives the output size
+    let probe = run(vec![0.0; tile_len])?;
+    if probe.height == 0 || probe.width == 0 {
+        return Err(invalid_data(format!("Tile of {tile_size}x{tile_size} is too small for this network")));
+    }
+    let mut error = None;
+    let output = tiling::run_tiled(image, height, width, (tile_size, tile_size, inputs), (probe.height, probe.width, probe.channels), 1, |xs| {
+        match run(xs.to_vec()) {
+            Ok(y) => y.values,
+            Err(e) => {
+                error = Some(e);
+                vec![0.0; probe.values.len()]
+            }
+        }
+    });
+    error.map_or(Ok(output), Err)
+}
+
+fn unet(checkpoint: &Checkpoint, x: FeatureMap, prefix: &str, depth: usize) -> io::Result<(FeatureMap, Vec<FeatureMap>)> {
+    let unet_config = checkpoint.config.model.unet;
+    let shortcut = |name: &str| (unet_config.block == Block::Residual).then(|| format!("{prefix}{name}"));
+    let x = block(checkpoint, x, &format!("{prefix}conv1"), &format!("{prefix}conv2"), shortcut("encoder_shortcut"))?;
+    let (x, maps) = if depth > 0 {
+        let (inner, inner_maps) = unet(checkpoint, downsample(checkpoint, &x, &format!("{prefix}downsample"))?, &format!("{prefix}inner."), depth - 1)?;
+        let skip = SkipAlignment::new((x.height, x.width), (inner.height, inner.width));
+        let align = |y: &FeatureMap| crop(&upsample(y, skip.factor_x, skip.factor_y), skip.left, skip.top, skip.right, skip.bottom);
+        let inner = align(&inner);
+        let mut maps = Vec::new();
+        let x = if unet_config.attention {
+            let (x, map) = attention_gate(checkpoint, &x, &inner, &format!("{prefix}attention"))?;
+            maps.push(map);
+            x
+        } else {
+            x
+        };
+        maps.extend(inner_maps.iter().map(align));
+        (concat(&x, &inner), maps)
+    } else {
+        (x, Vec::new())
+    };
+    let y = block(checkpoint, x, &format!("{prefix}conv3"), &format!("{prefix}conv4"), shortcut("decoder_shortcut"))?;
+    let maps = maps.iter().map(|map| center_crop(map, y.height, y.width)).collect();
+    Ok((y, maps))
+}
+
+//See UNetPlusPlus::eval, x[i][j] is node (i, j)
+fn unet_plus_plus(checkpoint: &Checkpoint, input: FeatureMap, depth: usize) -> io::Result<FeatureMap> {
+    let node = |x: FeatureMap, i: usize, j: usize| block(checkpoint, x, &format!("node{i}_{j}.conv1"), &format!("node{i}_{j}.conv2"), None);
+    let mut x = vec![vec![node(input, 0, 0)?]];
+    for i in 1 ..= depth {
+        let pooled = downsample(checkpoint, &x[i - 1][0], &format!("down{i}"))?;
+        x.push(vec![node(pooled, i, 0)?]);
+    }
+    for j in 1 ..= depth {
+        for i in 0 ..= depth - j {
+            let (height, width) = (x[i][j - 1].height, x[i][j - 1].width);
+            let below = &x[i + 1][j - 1];
+            let skip = SkipAlignment::new((height, width), (below.height, below.width));
+            let upsampled = crop(&upsample(below, skip.factor_x, skip.factor_y), skip.left, skip.top, skip.right, skip.bottom);
+            let inputs = x[i].iter().map(|earlier| center_crop(earlier, height, width)).reduce(|a, b| concat(&a, &b)).unwrap();
+            let y = node(concat(&inputs, &upsampled), i, j)?;
+            x[i].push(y);
+        }
+    }
+    Ok(leaky_relu(conv_layer(checkpoint, &x[0][depth], &format!("head{depth}"))?, 0.01))
+}
+
+//See unet::AttentionGate, returns the gated skip features and the attention map
+fn attention_gate(checkpoint: &Checkpoint, x: &FeatureMap, g: &FeatureMap, layer: &str) -> io::Result<(FeatureMap, FeatureMap)> {
+    let skip = conv_layer(checkpoint, x, &format!("{layer}.skip"))?;
+    let gate = conv_layer(checkpoint, g, &format!("{layer}.gate"))?;
+    let q = leaky_relu(FeatureMap { values: skip.values.iter().zip(gate.values.iter()).map(|(a, b)| a + b).collect(), ..skip }, 0.01);
+    let mut map = conv_layer(checkpoint, &q, &format!("{layer}.psi"))?;
+    map.values.iter_mut().for_each(|v| *v = 1.0 / (1.0 + (-*v).exp()));
+    let values = x.values.chunks(x.channels).zip(map.values.iter())
+        .flat_map(|(pixel, weight)| pixel.iter().map(move |v| v * weight))
+        .collect();
+    Ok((FeatureMap { values, ..x.clone() }, map))
+}
+
+fn center_crop(x: &FeatureMap, height: usize, width: usize) -> FeatureMap {
+    let (top, left) = ((x.height - height) / 2, (x.width - width) / 2);
+    crop(x, left, top, x.width - width - left, x.height - height - top)
+}
+
+//See unet::Downsample, layer is only used by strided convs
+fn downsample(checkpoint: &Checkpoint, x: &FeatureMap, layer: &str) -> io::Result<FeatureMap> {
+    let size = checkpoint.config.model.unet.pool_size;
+    Ok(match checkpoint.config.model.unet.downsampling {
+        Downsampling::Max => max_pool2d(x, size),
+        Downsampling::Average => average_pool2d(x, size),
+        Downsampling::StridedConv => leaky_relu(strided_conv_layer(checkpoint, x, layer, size)?, 0.01),
+    })
+}
+
+//See unet::block
+fn block(checkpoint: &Checkpoint, x: FeatureMap, first: &str, second: &str, shortcut: Option<String>) -> io::Result<FeatureMap> {
+    let y = conv_layer(checkpoint, &leaky_relu(conv_layer(checkpoint, &x, first)?, 0.01), second)?;
+    let y = match shortcut {
+        Some(shortcut) => {
+            let projected = conv_layer(checkpoint, &center_crop(&x, y.height, y.width), &shortcut)?;
+            FeatureMap { values: y.values.iter().zip(projected.values.iter()).map(|(a, b)| a + b).collect(), ..y }
+        }
+        None => y,
+    };
+    Ok(leaky_relu(y, 0.01))
+}
+
+//Conv without activation
+fn conv_layer(checkpoint: &Checkpoint, x: &FeatureMap, layer: &str) -> io::Result<FeatureMap> {
+    strided_conv_layer(checkpoint, x, layer, 1)
+}
+
+fn strided_conv_layer(checkpoint: &Checkpoint, x: &FeatureMap, layer: &str, stride: usize) -> io::Result<FeatureMap> {
+    let tensor = |name: String| checkpoint.get(&name).ok_or_else(|| invalid_data(format!("Parameter {name} not found in checkpoint")));
+    let filter = tensor(format!("{layer}.filter"))?;
+    let bias = tensor(format!("{layer}.bias"))?;
+    Ok(strided_conv2d(x, filter, bias, stride))
+}
+
+//Valid cross-correlation with a descent filter of shape [groups, outputs per group, kh, kw, inputs per group]
+pub fn conv2d(x: &FeatureMap, filter: &Tensor, bias: &Tensor) -> FeatureMap {
+    strided_conv2d(x, filter, bias, 1)
+}
+
+//conv2d that only evaluates every stride-th position in both directions
+pub fn strided_conv2d(x: &FeatureMap, filter: &Tensor, bias: &Tensor, stride: usize) -> FeatureMap {
+    let rank = filter.shape.len();
+    let [outputs, kh, kw, inputs]: [usize; 4] = filter.shape[rank - 4 ..].try_into().unwrap();
+    let groups = filter.shape[.. rank - 4].iter().product::<usize>();
+    assert_eq!(groups * inputs, x.channels, "{} expects {} input channels", filter.name, groups * inputs);
+    let valid = |size: usize, k: usize| if size < k { 0 } else { (size - k) / stride + 1 };
+    let (height, width) = (valid(x.height, kh), valid(x.width, kw));
+    let mut values = Vec::with_capacity(height * width * groups * outputs);
+    for y in 0 .. height {
+        for x0 in 0 .. width {
+            for o in 0 .. groups * outputs {
+                let group_start = (o / outputs) * inputs;
+                let mut sum = bias.values[o];
+                for ky in 0 .. kh {
+                    for kx in 0 .. kw {
+                        let pixel = &x.at(y * stride + ky, x0 * stride + kx)[group_start .. group_start + inputs];
+                        let weights = &filter.values[((o * kh + ky) * kw + kx) * inputs ..][.. inputs];
+                        sum += pixel.iter().zip(weights.iter()).map(|(a, b)| a * b).sum::<f32>();
+                    }
+                }
+                values.push(sum);
+            }
+        }
+    }
+    FeatureMap::new(values, height, width, groups * outputs)
+}
+
+pub fn leaky_relu(mut x: FeatureMap, alpha: f32) -> FeatureMap {
+    x.values.iter_mut().for_each(|v| if *v < 0.0 { *v *= alpha });
+    x
+}
+
+//size x size windows with stride size, rows and columns that do not fill a window are dropped
+pub fn max_pool2d(x: &FeatureMap, size: usize) -> FeatureMap {
+    pool2d(x, size, |window| window.iter().copied().fold(f32::NEG_INFINITY, f32::max))
+}
+
+pub fn average_pool2d(x: &FeatureMap, size: usize) -> FeatureMap {
+    pool2d(x, size, |window| window.iter().sum::<f32>() / window.len() as f32)
+}
+
+fn pool2d(x: &FeatureMap, size: usize, reduce: impl Fn(&[f32]) -> f32) -> FeatureMap {
+    let (height, width) = (x.height / size, x.width / size);
+    let mut values = Vec::with_capacity(height * width * x.channels);
+    for y in 0 .. height {
+        for x0 in 0 .. width {
+            for c in 0 .. x.channels {
+                let window = (0 .. size * size).map(|i| x.at(y * size + i / size, x0 * size + i % size)[c]).collect::<Vec<_>>();
+                values.push(reduce(&window));
+            }
+        }
+    }
+    FeatureMap::new(values, height, width, x.channels)
+}
+
+//Nearest neighbour, every pixel becomes a factor_y x factor_x block
+pub fn upsample(x: &FeatureMap, factor_x: usize, factor_y: usize) -> FeatureMap {
+    let (height, width) = (x.height * factor_y, x.width * factor_x);
+    let mut values = Vec::with_capacity(height * width * x.channels);
+    for y in 0 .. height {
+        for x0 in 0 .. width {
+            values.extend_from_slice(x.at(y / factor_y, x0 / factor_x));
+        }
+    }
+    FeatureMap::new(values, height, width, x.channels)
+}
+
+//Removes the given number of pixels from each side
+pub fn crop(x: &FeatureMap, left: usize, top: usize, right: usize, bottom: usize) -> FeatureMap {
+    let (height, width) = (x.height - top - bottom, x.width - left - right);
+    let mut values = Vec::with_capacity(height * width * x.channels);
+    for y in top .. top + height {
+        let start = (y * x.width + left) * x.channels;
+        values.extend_from_slice(&x.values[start .. start + width * x.channels]);
+    }
+    FeatureMap::new(values, height, width, x.channels)
+}
+
+//Stacks the channels of b behind those of a
+pub fn concat(a: &FeatureMap, b: &FeatureMap) -> FeatureMap {
+    assert_eq!((a.height, a.width), (b.height, b.width), "Concatenated feature maps differ in size");
+    let values = a.values.chunks(a.channels).zip(b.values.chunks(b.channels))
+        .flat_map(|(pa, pb)| pa.iter().chain(pb.iter()).copied())
+        .collect();
+    FeatureMap::new(values, a.height, a.width, a.channels + b.channels)
+}
@@ -0,0 +1,59 @@
+use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
+
+//A single run seed, split into independent streams so that e.g. turning on augmentation
+//does not change the initial weights or the order of the training tiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunSeed(pub u64);
+
+impl RunSeed {
+    //Uses the configured seed or draws a fresh one, either way it should be logged so the run can be repeated
+    pub fn from_config(seed: Option<u64>) -> Self {
+        Self(seed.unwrap_or_else(|| thread_rng().gen()))
+    }
+
+    //Weight initialization via env.reset_parameter
+    pub fn init_rng(&self) -> StdRng {
+        self.stream(1)
+    }
+
+    //Picking samples and tile positions
+    pub fn data_rng(&self) -> StdRng {
+        self.stream(2)
+    }
+
+    //Random flips and other augmentations
+    pub fn augmentation_rng(&self) -> StdRng {
+        self.stream(3)
+    }
+
+    //Per-run seeds passed to env.run
+    pub fn graph_rng(&self) -> StdRng {
+        self.stream(4)
+    }
+
+    //Data and augmentation streams of a single training batch, so batches can be produced
+    //on any thread in any order and still come out the same
+    pub fn batch_rngs(&self, batch: u64) -> (StdRng, StdRng) {
+        let batch_seed = RunSeed(self.0 ^ splitmix64(batch.wrapping_add(0x5EED)));
+        (batch_seed.data_rng(), batch_seed.augmentation_rng())
+    }
+
+    fn stream(&self, index: u64) -> StdRng {
+        StdRng::seed_from_u64(splitmix64(self.0 ^ splitmix64(index)))
+    }
+}
+
+//Scrambles nearby seeds into unrelated ones
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+//Box-Muller
+pub fn standard_normal(rng: &mut impl Rng) -> f32 {
+    let u1: f32 = 1.0 - rng.gen::<f32>();
+    let u2: f32 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
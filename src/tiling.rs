@@ -0,0 +1,165 @@
+use crate::network;
+use descent::{module::*, prelude::*};
+use rand::RngCore;
+use std::io::Write;
+
+//Overlap-tile inference as in the original U-Net paper:
+//the valid convolutions lose a border around every tile, so the image is mirror-padded by that border
+//and tiled with a stride of the output size, which makes the stitched output cover the whole image
+pub struct TiledInference {
+    input_param: Parameter,
+    output_param: Parameter,
+    graph: Graph,
+    batch_size: usize,
+    tile_height: usize,
+    tile_width: usize,
+    input_channels: usize,
+    output_height: usize,
+    output_width: usize,
+    output_channels: usize,
+}
+
+impl TiledInference {
+    pub fn new(env: &mut Environment, module: &impl Module, batch_size: usize, tile_height: usize, tile_width: usize, input_channels: usize) -> Self {
+        let input_param = env.static_parameter([batch_size, tile_height, tile_width, input_channels], "tile_input");
+
+        let output_shape = network::output_shape(env, |scope| module.test(scope.parameter(&input_param)));
+        let [_, output_height, output_width, output_channels]: [usize; 4] = output_shape.try_into().unwrap();
+        assert!(output_height > 0 && output_width > 0, "Tile of {tile_width}x{tile_height} is too small for this network");
+
+        let output_param = env.static_parameter(output_shape, "tile_output");
+
+        let graph = env.build_graph(|scope| {
+            let input = scope.parameter(&input_param);
+            let output = module.test(input);
+            scope.write_parameter_value(&output_param, output.value());
+        });
+
+        Self {
+            input_param,
+            output_param,
+            graph,
+            batch_size,
+            tile_height,
+            tile_width,
+            input_channels,
+            output_height,
+            output_width,
+            output_channels,
+        }
+    }
+
+    //Number of pixels lost on the top and left side of every tile
+    pub fn border(&self) -> (usize, usize) {
+        ((self.tile_height - self.output_height) / 2, (self.tile_width - self.output_width) / 2)
+    }
+
+    //Takes an image in HWC layout and returns the prediction for the full image, also in HWC layout
+    pub fn run(&self, env: &mut Environment, image: &[f32], height: usize, width: usize, rng: &mut impl RngCore) -> Vec<f32> {
+        let tile = (self.tile_height, self.tile_width, self.input_channels);
+        let output = (self.output_height, self.output_width, self.output_channels);
+        run_tiled(image, height, width, tile, output, self.batch_size, |xs| {
+            let mut x_writer = env.writer(&self.input_param);
+            x_writer.write_all(bytemuck::cast_slice(xs)).unwrap();
+            drop(x_writer);
+
+            env.run(&self.graph, rng.next_u32());
+            env.read_parameter_to_vec(&self.output_param)
+        })
+    }
+
+    pub fn output_channels(&self) -> usize {
+        self.output_channels
+    }
+}
+
+//The tiling behind TiledInference::run for any way of running a batch of tiles: tile and output are
+//(height, width, channels), run_batch maps batch_size input tiles to batch_size output tiles
+pub fn run_tiled(
+    image: &[f32],
+    height: usize,
+    width: usize,
+    (tile_height, tile_width, input_channels): (usize, usize, usize),
+    (output_height, output_width, output_channels): (usize, usize, usize),
+    batch_size: usize,
+    mut run_batch: impl FnMut(&[f32]) -> Vec<f32>,
+) -> Vec<f32> {
+    assert!(height > 0 && width > 0, "Can not tile an empty {width}x{height} image");
+    assert_eq!(image.len(), height * width * input_channels);
+
+    let tiles_y = height.div_ceil(output_height);
+    let tiles_x = width.div_ceil(output_width);
+
+    //Pad so that the last row and column of tiles fit completely into the padded image
+    let (top, left) = ((tile_height - output_height) / 2, (tile_width - output_width) / 2);
+    let bottom = tiles_y * output_height + tile_height - output_height - top - height;
+    let right = tiles_x * output_width + tile_width - output_width - left - width;
+    let padded = mirror_pad(image, height, width, input_channels, (top, bottom, left, right));
+    let padded_width = left + width + right;
+
+    let tiles = (0 .. tiles_y)
+        .flat_map(|ty| (0 .. tiles_x).map(move |tx| (ty, tx)))
+        .collect::<Vec<_>>();
+
+    let tile_len = tile_height * tile_width * input_channels;
+    let output_tile_len = output_height * output_width * output_channels;
+    let mut output = vec![0.0; height * width * output_channels];
+
+    for batch in tiles.chunks(batch_size) {
+        //Unused batch entries stay zero
+        let mut xs = vec![0.0f32; batch_size * tile_len];
+        for ((ty, tx), x) in batch.iter().zip(xs.chunks_mut(tile_len)) {
+            let y0 = ty * output_height;
+            let x0 = tx * output_width;
+            for y in 0 .. tile_height {
+                let row_start = ((y0 + y) * padded_width + x0) * input_channels;
+                let row_len = tile_width * input_channels;
+                x[y * row_len .. (y + 1) * row_len].copy_from_slice(&padded[row_start .. row_start + row_len]);
+            }
+        }
+
+        let ys = run_batch(&xs);
+
+        //Stitch the tiles back together, dropping whatever overhangs the original image
+        for ((ty, tx), y_tile) in batch.iter().zip(ys.chunks(output_tile_len)) {
+            let y0 = ty * output_height;
+            let x0 = tx * output_width;
+            let rows = output_height.min(height - y0);
+            let cols = output_width.min(width - x0);
+            for y in 0 .. rows {
+                let src = y * output_width * output_channels;
+                let dst = ((y0 + y) * width + x0) * output_channels;
+                output[dst .. dst + cols * output_channels].copy_from_slice(&y_tile[src .. src + cols * output_channels]);
+            }
+        }
+    }
+
+    output
+}
+
+//Reflects an index into 0 .. n without repeating the edge pixel, like numpy's "reflect" mode
+pub fn mirror_index(i: isize, n: usize) -> usize {
+    if n == 1 {
+        return 0;
+    }
+    let period = 2 * (n as isize - 1);
+    let i = i.rem_euclid(period);
+    if i < n as isize { i as usize } else { (period - i) as usize }
+}
+
+//Mirror-pads an HWC image, padding may be larger than the image itself
+pub fn mirror_pad(image: &[f32], height: usize, width: usize, channels: usize, (top, bottom, left, right): (usize, usize, usize, usize)) -> Vec<f32> {
+    assert!(height > 0 && width > 0, "Can not mirror an empty {width}x{height} image");
+    let padded_height = top + height + bottom;
+    let padded_width = left + width + right;
+    let mut padded = Vec::with_capacity(padded_height * padded_width * channels);
+    for y in 0 .. padded_height {
+        let src_y = mirror_index(y as isize - top as isize, height);
+        for x in 0 .. padded_width {
+            let src_x = mirror_index(x as isize - left as isize, width);
+            let src = (src_y * width + src_x) * channels;
+            padded.extend_from_slice(&image[src .. src + channels]);
+        }
+    }
+    padded
+}
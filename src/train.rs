@@ -0,0 +1,142 @@
+use crate::{checkpoint::{invalid_data, Checkpoint}, config::{ExperimentConfig, LossConfig, Normalization}, data, init, loader::{Batch, Loader}, network::{self, Network}, seed::RunSeed};
+use descent::{module::*, prelude::*, optimizer::*};
+use rand::RngCore;
+use std::{fs, io::{self, Write}, ops::Sub, path::Path};
+
+//Per-sample loss, the spatial and channel axes are summed up
+pub fn loss<'s, T>(config: &LossConfig, prediction: DualArray<'s>, target: T) -> DualArray<'s>
+where
+    DualArray<'s>: Sub<T, Output = DualArray<'s>>,
+{
+    let error = match *config {
+        LossConfig::Mse => (prediction - target).square(),
+        LossConfig::Charbonnier { epsilon } => ((prediction - target).square() + epsilon * epsilon).sqrt(),
+    };
+    error
+        .reduce_sum(-1, false)
+        .reduce_sum(-1, false)
+        .reduce_sum(-1, false)
+}
+
+//Per-channel statistics of the network inputs over the whole dataset
+fn input_statistics(config: &ExperimentConfig) -> io::Result<Normalization> {
+    let inputs = config.dataset.images.iter().map(|path| match config.dataset.colorize {
+        Some(space) => {
+            let (rgb, _, _) = data::load_image(path, 3).map_err(|e| invalid_data(format!("{}: {e}", path.display())))?;
+            Ok(space.split(&rgb).0)
+        }
+        None => data::load_input(path, config.model.inputs).map(|(values, _, _)| values),
+    });
+    data::channel_statistics(inputs, config.model.inputs)
+}
+
+//Trains on random tiles of the dataset, writes checkpoints into output_dir and returns the mean loss of every epoch
+pub fn train(config: &ExperimentConfig, output_dir: &Path) -> Vec<f32> {
+    //Checkpoints record the seed that was actually used, even if the config did not set one
+    let seed = RunSeed::from_config(config.seed);
+    eprintln!("Seed {}", seed.0);
+    let mut config = ExperimentConfig { seed: Some(seed.0), ..config.clone() };
+    //The statistics become part of the model config, so every checkpoint (and predict) normalizes the same way.
+    //Multi-band rasters have arbitrary value ranges and are always normalized
+    let dataset = &config.dataset;
+    if config.model.normalization.is_none() && (dataset.normalize || dataset.images.iter().any(data::is_raster)) {
+        let normalization = input_statistics(&config).unwrap_or_else(|e| panic!("Could not compute input statistics: {e}"));
+        eprintln!("Input means {:?} stds {:?}", normalization.mean, normalization.std);
+        config.model.normalization = Some(normalization);
+    }
+    let config = &config;
+    let mut init_rng = seed.init_rng();
+    let mut graph_rng = seed.graph_rng();
+
+    let mut env = Environment::new();
+    let network = Network::new(&mut env, &config.model);
+    let model = &config.model;
+    let dataset = &config.dataset;
+
+    let batch_size = dataset.batch_size;
+    let tile = dataset.tile_size;
+    let input_param = env.static_parameter([batch_size, tile, tile, model.inputs], "input");
+
+    let output_shape = network::output_shape(&env, |scope| network.test(scope.parameter(&input_param)));
+    let [_, out_h, out_w, _]: [usize; 4] = output_shape.try_into().unwrap();
+    let border_y = (tile - out_h) / 2;
+    let border_x = (tile - out_w) / 2;
+    eprintln!("Tiles of {tile}x{tile} predict {out_w}x{out_h}");
+
+    let target_param = env.static_parameter(output_shape, "target");
+    let loss_param = env.static_parameter([1], "loss");
+
+    //Create training graph
+    let (train_graph, parameters, _optimizer) = {
+        let scope = env.scope();
+        //With deep supervision every output is compared with the same target, the auxiliary losses are scaled by their weight
+        let outputs = network.eval_outputs(scope.parameter(&input_param), &EvalContext { is_training: true });
+        let target = scope.parameter(&target_param);
+        let loss = outputs.into_iter().enumerate()
+            .map(|(i, x)| match i {
+                0 => loss(&config.loss, x, target),
+                _ => loss(&config.loss, x, target) * model.unet.auxiliary_weight,
+            })
+            .reduce(|total, loss| total + loss)
+            .unwrap()
+            .set_loss();
+        scope.update_parameter_value(&loss_param, |loss_sum| {
+            loss_sum + loss.reduce_sum(0, false)
+        });
+        let parameters = scope.trainable_parameters();
+        let optimizer = Adam::new(
+            &mut env,
+            &scope,
+            &parameters,
+            config.optimizer.learning_rate,
+            config.optimizer.beta1,
+            config.optimizer.beta2,
+            config.optimizer.epsilon
+        );
+        (scope.build_graph(), parameters, optimizer)
+    };
+    let names = network.parameter_names();
+    assert_eq!(names.len(), parameters.len());
+
+    for param in parameters.iter() {
+        env.reset_parameter(param, &mut init_rng);
+    }
+    init::initialize(&mut env, &network, &names, &parameters, &model.init, &mut init_rng);
+
+    fs::create_dir_all(output_dir).expect("Could not create output directory");
+
+    //Images are decoded and augmented on worker threads while the graph runs
+    let output_window = (border_y, border_x, out_h, out_w);
+    let mut loader = Loader::new(dataset, model, &config.augmentation, output_window, seed, config.epochs * config.schedule.steps_per_epoch);
+
+    let mut epoch_losses = Vec::with_capacity(config.epochs);
+    eprintln!("Starting training");
+    for epoch in 1 ..= config.epochs {
+        let mut epoch_loss = 0.0;
+        for batch in 0 .. config.schedule.steps_per_epoch {
+            let Batch { inputs, targets } = loader.next().expect("Data loader stopped early");
+            let mut x_writer = env.writer(&input_param);
+            x_writer.write_all(bytemuck::cast_slice(&inputs)).unwrap();
+            drop(x_writer);
+            let mut y_writer = env.writer(&target_param);
+            y_writer.write_all(bytemuck::cast_slice(&targets)).unwrap();
+            drop(y_writer);
+
+            env.writer(&loss_param).zero_fill();
+            env.run(&train_graph, graph_rng.next_u32());
+            let loss = env.read_parameter_scalar(&loss_param) / output_shape.iter().product::<usize>() as f32;
+            epoch_loss += loss;
+            eprint!("\rEpoch {epoch} Batch {batch} Loss={}      ", loss);
+        }
+        eprintln!();
+        epoch_losses.push(epoch_loss / config.schedule.steps_per_epoch as f32);
+
+        if epoch % config.schedule.checkpoint_every == 0 || epoch == config.epochs {
+            let checkpoint = Checkpoint::from_parameters(&mut env, config.clone(), epoch, &names, &parameters);
+            let extension = config.schedule.checkpoint_format.extension();
+            checkpoint.save(output_dir.join(format!("checkpoint_epoch_{epoch:03}.{extension}"))).expect("Could not save checkpoint");
+            checkpoint.save(output_dir.join(format!("checkpoint.{extension}"))).expect("Could not save checkpoint");
+        }
+    }
+    epoch_losses
+}
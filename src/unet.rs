@@ -0,0 +1,309 @@
+use crate::{config::{Block, DropoutConfig, Downsampling, UNetConfig}, dropout::Dropout};
+use descent::{module::*, prelude::*};
+
+//Unet definition, recursively holds all the conv layers
+pub struct UNet {
+    conv1: Conv2D,
+    conv2: Conv2D,
+    //1x1 projections around conv1/conv2 and conv3/conv4 for residual blocks
+    encoder_shortcut: Option<Conv2D>,
+    //Applied to the encoder block output, before it is used as skip connection and downsampled
+    dropout: Option<Dropout>,
+    inner: 
+        Option<(
+            Downsample,
+            Box<Self>
+        )>,
+    attention: Option<AttentionGate>,
+    conv3: Conv2D,
+    conv4: Conv2D,
+    decoder_shortcut: Option<Conv2D>,
+    //1x1 head on the output of the inner level for deep supervision
+    auxiliary: Option<Conv2D>,
+}
+impl UNet {
+    //Builder method
+    pub fn new(env: &mut Environment, inputs: usize, outputs: usize, depth: usize, width: usize, kernelsize: usize) -> Self {
+        Self::with_config(env, inputs, outputs, depth, width, kernelsize, UNetConfig::default())
+    }
+
+    //Same as new with the options of the model config
+    pub fn with_config(env: &mut Environment, inputs: usize, outputs: usize, depth: usize, width: usize, kernelsize: usize, config: UNetConfig) -> Self {
+        Self::level(env, inputs, outputs, depth, width, kernelsize, config, outputs)
+    }
+
+    //predictions is the number of outputs of the outermost level, which the auxiliary heads produce as well
+    #[allow(clippy::too_many_arguments)]
+    fn level(env: &mut Environment, inputs: usize, outputs: usize, depth: usize, width: usize, kernelsize: usize, config: UNetConfig, predictions: usize) -> Self {
+        let residual = config.block == Block::Residual;
+        let conv1 = Conv2D::builder(inputs, width, kernelsize, kernelsize).build(env);
+        let conv2 = Conv2D::builder(width, width, kernelsize, kernelsize).build(env);
+        let encoder_shortcut = residual.then(|| Conv2D::builder(inputs, width, 1, 1).build(env));
+        let inner = if depth > 0 {Some((
+            Downsample::new(env, width, config),
+            Box::new(Self::level(env, width, width * 2, depth - 1, width * 2, kernelsize, config, predictions))
+        ))} else {None};
+        let attention = (config.attention && depth > 0).then(|| AttentionGate::new(env, width, width * 2, width));
+        let decoder_inputs = if depth > 0 {width * 3} else {width};
+        let conv3 = Conv2D::builder(decoder_inputs, width, kernelsize, kernelsize).build(env);
+        let conv4 = Conv2D::builder(width, outputs, kernelsize, kernelsize).build(env);
+        let decoder_shortcut = residual.then(|| Conv2D::builder(decoder_inputs, outputs, 1, 1).build(env));
+        let auxiliary = (config.deep_supervision && depth > 0).then(|| Conv2D::builder(width * 2, predictions, 1, 1).build(env));
+        Self { conv1, conv2, encoder_shortcut, dropout: None, inner, attention, conv3, conv4, decoder_shortcut, auxiliary }
+    }
+
+    //Adds dropout to the levels the config lists, 0 is this level
+    pub fn with_dropout(mut self, config: &DropoutConfig) -> Self {
+        self.set_dropout(config, 0);
+        self
+    }
+
+    fn set_dropout(&mut self, config: &DropoutConfig, level: usize) {
+        self.dropout = Dropout::at_level(config, level);
+        if let Some((_, inner)) = self.inner.as_mut() {
+            inner.set_dropout(config, level + 1);
+        }
+    }
+
+    //Names of the trainable parameters in the order they are created in with_config(),
+    //which is also the order scope.trainable_parameters() returns them in
+    pub fn parameter_names(&self) -> Vec<String> {
+        let layer = |layer: &str| [format!("{layer}.filter"), format!("{layer}.bias")];
+        let mut names = Vec::new();
+        names.extend(layer("conv1"));
+        names.extend(layer("conv2"));
+        if self.encoder_shortcut.is_some() {
+            names.extend(layer("encoder_shortcut"));
+        }
+        if let Some((downsample, inner)) = self.inner.as_ref() {
+            if downsample.is_trainable() {
+                names.extend(layer("downsample"));
+            }
+            names.extend(inner.parameter_names().into_iter().map(|name| format!("inner.{name}")));
+        }
+        if self.attention.is_some() {
+            for gate_layer in ["attention.skip", "attention.gate", "attention.psi"] {
+                names.extend(layer(gate_layer));
+            }
+        }
+        names.extend(layer("conv3"));
+        names.extend(layer("conv4"));
+        if self.decoder_shortcut.is_some() {
+            names.extend(layer("decoder_shortcut"));
+        }
+        if self.auxiliary.is_some() {
+            names.extend(layer("auxiliary"));
+        }
+        names
+    }
+
+    //Number of attention maps eval_with_attention returns
+    pub fn attention_levels(&self) -> usize {
+        let inner = self.inner.as_ref().map_or(0, |(_, inner)| inner.attention_levels());
+        inner + self.attention.is_some() as usize
+    }
+
+    //Number of outputs eval_outputs returns, the prediction and one per auxiliary head
+    pub fn output_count(&self) -> usize {
+        let inner = self.inner.as_ref().map_or(0, |(_, inner)| inner.output_count() - 1);
+        1 + inner + self.auxiliary.is_some() as usize
+    }
+
+    //eval that also returns the attention map of every gated level, outermost first,
+    //each brought to the output size the same way the inner outputs are
+    pub fn eval_with_attention<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> (DualArray<'s>, Vec<DualArray<'s>>) {
+        let levels = self.eval_levels(input, ctx);
+        (levels.output, levels.attention)
+    }
+
+    //The prediction followed by the auxiliary predictions of the inner levels, outermost first and
+    //aligned to the prediction like the attention maps
+    pub fn eval_outputs<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> Vec<DualArray<'s>> {
+        let levels = self.eval_levels(input, ctx);
+        [vec![levels.output], levels.auxiliary].concat()
+    }
+
+    fn eval_levels<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> Levels<'s> {
+        let x = block(input, &self.conv1, &self.conv2, self.encoder_shortcut.as_ref(), ctx);
+        let x = match self.dropout.as_ref() {
+            Some(dropout) => x.apply(dropout, ctx),
+            None => x,
+        };
+        let (x, maps, auxiliary) = if let Some((pool, inner)) = self.inner.as_ref() {
+            let [_, h_outer, w_outer, _]: [usize; 4] = x.shape().try_into().unwrap();
+
+            let x_inner = x.apply(pool, ctx);
+            let Levels { output: x_inner, attention: inner_maps, auxiliary: inner_auxiliary } = inner.eval_levels(x_inner, ctx);
+            let [_, h_inner, w_inner, _]: [usize; 4] = x_inner.shape().try_into().unwrap();
+            let skip = SkipAlignment::new((h_outer, w_outer), (h_inner, w_inner));
+            assert_eq!(h_inner * skip.factor_y, w_inner * skip.factor_x);
+            let align = |y: DualArray<'s>| y.upsample(skip.factor_x, skip.factor_y).crop(
+                skip.left, skip.top, skip.right, skip.bottom
+            );
+            let mut auxiliary = Vec::new();
+            if let Some(head) = self.auxiliary.as_ref() {
+                auxiliary.push(align(x_inner.apply(head, ctx).leaky_relu(0.01)));
+            }
+            auxiliary.extend(inner_auxiliary.into_iter().map(align));
+            let x_inner = align(x_inner);
+            let mut maps = Vec::new();
+            let x = match self.attention.as_ref() {
+                Some(gate) => {
+                    let (x, map) = gate.eval(x, x_inner, ctx);
+                    maps.push(map);
+                    x
+                }
+                None => x,
+            };
+            maps.extend(inner_maps.into_iter().map(align));
+            (x.concat(x_inner, -1), maps, auxiliary)
+        } else {
+            (x, Vec::new(), Vec::new())
+        };
+        let y = block(x, &self.conv3, &self.conv4, self.decoder_shortcut.as_ref(), ctx);
+        let [_, height, width, _]: [usize; 4] = y.shape().try_into().unwrap();
+        let crop = |xs: Vec<DualArray<'s>>| xs.into_iter().map(|x| center_crop(x, height, width)).collect();
+        Levels { output: y, attention: crop(maps), auxiliary: crop(auxiliary) }
+    }
+}
+
+//What eval_levels collects from a level and everything inside it
+struct Levels<'s> {
+    output: DualArray<'s>,
+    attention: Vec<DualArray<'s>>,
+    auxiliary: Vec<DualArray<'s>>,
+}
+
+//Additive attention gate of Attention U-Net: the skip features x are weighted by
+//sigmoid(psi(act(W_x x + W_g g))), where g is the aligned output of the inner level
+struct AttentionGate {
+    skip: Conv2D,
+    gate: Conv2D,
+    psi: Conv2D,
+}
+
+impl AttentionGate {
+    fn new(env: &mut Environment, skip_channels: usize, gate_channels: usize, channels: usize) -> Self {
+        Self {
+            skip: Conv2D::builder(skip_channels, channels, 1, 1).build(env),
+            gate: Conv2D::builder(gate_channels, channels, 1, 1).build(env),
+            psi: Conv2D::builder(channels, 1, 1, 1).build(env),
+        }
+    }
+
+    //Gated skip features and the single channel attention map
+    fn eval<'s>(&self, x: DualArray<'s>, g: DualArray<'s>, ctx: &EvalContext) -> (DualArray<'s>, DualArray<'s>) {
+        let q = (x.apply(&self.skip, ctx) + g.apply(&self.gate, ctx)).leaky_relu(0.01);
+        let map = q.apply(&self.psi, ctx).sigmoid();
+        (x * map, map)
+    }
+}
+
+pub(crate) fn center_crop(x: DualArray<'_>, height: usize, width: usize) -> DualArray<'_> {
+    let [_, h, w, _]: [usize; 4] = x.shape().try_into().unwrap();
+    let (top, left) = ((h - height) / 2, (w - width) / 2);
+    x.crop(left, top, w - width - left, h - height - top)
+}
+
+//The step from one encoder level to the next, see config::Downsampling
+pub(crate) enum Downsample {
+    Max(usize),
+    Average(usize),
+    StridedConv(Conv2D),
+}
+
+impl Downsample {
+    pub(crate) fn new(env: &mut Environment, channels: usize, config: UNetConfig) -> Self {
+        let size = config.pool_size;
+        match config.downsampling {
+            Downsampling::Max => Downsample::Max(size),
+            Downsampling::Average => Downsample::Average(size),
+            Downsampling::StridedConv => {
+                Downsample::StridedConv(Conv2D::builder(channels, channels, size, size).with_stride(size, size).build(env))
+            }
+        }
+    }
+
+    //Whether it adds a layer to parameter_names
+    pub(crate) fn is_trainable(&self) -> bool {
+        matches!(self, Downsample::StridedConv(_))
+    }
+}
+
+impl Module for Downsample {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        match self {
+            Downsample::Max(size) => input.max_pool2d((*size, *size), (*size, *size)),
+            Downsample::Average(size) => {
+                //Windows do not overlap, so after dropping the remainder the pixels of a window can be
+                //gathered by reshaping and summed up
+                let size = *size;
+                let [n, h, w, c]: [usize; 4] = input.shape().try_into().unwrap();
+                let (h_out, w_out) = (h / size, w / size);
+                input
+                    .crop(0, 0, w - w_out * size, h - h_out * size)
+                    .reshape([n, h_out, size, w_out, size * c])
+                    .reduce_sum(2, false)
+                    .reshape([n, h_out, w_out, size, c])
+                    .reduce_sum(3, false)
+                    / (size * size) as f32
+            }
+            Downsample::StridedConv(conv) => input.apply(conv, ctx).leaky_relu(0.01),
+        }
+    }
+}
+
+//Two convs with leaky relus, in residual blocks the projected and cropped input is added before the second one
+pub(crate) fn block<'s>(x: DualArray<'s>, first: &Conv2D, second: &Conv2D, shortcut: Option<&Conv2D>, ctx: &EvalContext) -> DualArray<'s> {
+    let y = x.apply(first, ctx).leaky_relu(0.01).apply(second, ctx);
+    let y = match shortcut {
+        Some(shortcut) => {
+            let [_, h_in, w_in, _]: [usize; 4] = x.shape().try_into().unwrap();
+            let [_, h_out, w_out, _]: [usize; 4] = y.shape().try_into().unwrap();
+            let (top, left) = ((h_in - h_out) / 2, (w_in - w_out) / 2);
+            y + x.crop(left, top, w_in - w_out - left, h_in - h_out - top).apply(shortcut, ctx)
+        }
+        None => y,
+    };
+    y.leaky_relu(0.01)
+}
+
+//How the output of an inner level is brought back to the size of the skip connection:
+//nearest upsampling by whole factors, then a centered crop of whatever overhangs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkipAlignment {
+    pub factor_x: usize,
+    pub factor_y: usize,
+    pub left: usize,
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+}
+
+impl SkipAlignment {
+    //Sizes are (height, width)
+    pub fn new((h_outer, w_outer): (usize, usize), (h_inner, w_inner): (usize, usize)) -> Self {
+        let factor_x = w_outer.div_ceil(w_inner);
+        let factor_y = h_outer.div_ceil(h_inner);
+        let (w_up, h_up) = (w_inner * factor_x, h_inner * factor_y);
+        let left = (w_up - w_outer) / 2;
+        let top = (h_up - h_outer) / 2;
+        Self { factor_x, factor_y, left, top, right: w_up - w_outer - left, bottom: h_up - h_outer - top }
+    }
+}
+
+impl Module for UNet {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        self.eval_with_attention(input, ctx).0
+    }
+}
+
+//The prediction with the attention maps of UNet::eval_with_attention appended as extra channels
+pub struct WithAttentionMaps<'a>(pub &'a UNet);
+
+impl Module for WithAttentionMaps<'_> {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        let (y, maps) = self.0.eval_with_attention(input, ctx);
+        maps.into_iter().fold(y, |y, map| y.concat(map, -1))
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{config::{DropoutConfig, UNetConfig}, dropout::Dropout, unet::{block, center_crop, Downsample, SkipAlignment}};
+use descent::{module::*, prelude::*};
+
+//UNet++ (Zhou et al.): instead of one skip per level, node (i, j) at depth i of decoder column j sees
+//every earlier node of its depth plus the upsampled node (i + 1, j - 1). Column 0 is the encoder,
+//node (0, depth) produces the output and with deep supervision every other top node gets a head as well
+pub struct UNetPlusPlus {
+    //nodes[i][j], two convs each with width * 2^i channels
+    nodes: Vec<Vec<(Conv2D, Conv2D)>>,
+    //downsample[i - 1] leads from node (i - 1, 0) to node (i, 0)
+    downsample: Vec<Downsample>,
+    //dropout[i] follows encoder node (i, 0)
+    dropout: Vec<Option<Dropout>>,
+    //1x1 convs on top nodes (0, j), the last one is the output
+    heads: Vec<(usize, Conv2D)>,
+}
+
+impl UNetPlusPlus {
+    //Builder method, takes deep_supervision, downsampling and pool_size from the config
+    pub fn new(env: &mut Environment, inputs: usize, outputs: usize, depth: usize, width: usize, kernelsize: usize, config: UNetConfig) -> Self {
+        let channels = |i: usize| width << i;
+        let pair = |env: &mut Environment, inputs: usize, outputs: usize| (
+            Conv2D::builder(inputs, outputs, kernelsize, kernelsize).build(env),
+            Conv2D::builder(outputs, outputs, kernelsize, kernelsize).build(env),
+        );
+        let mut nodes = (0 ..= depth).map(|_| Vec::new()).collect::<Vec<_>>();
+        let mut downsample = Vec::with_capacity(depth);
+        for (i, column) in nodes.iter_mut().enumerate() {
+            if i > 0 {
+                downsample.push(Downsample::new(env, channels(i - 1), config));
+            }
+            column.push(pair(env, if i == 0 { inputs } else { channels(i - 1) }, channels(i)));
+        }
+        for j in 1 ..= depth {
+            for (i, column) in nodes.iter_mut().enumerate().take(depth - j + 1) {
+                column.push(pair(env, j * channels(i) + channels(i + 1), channels(i)));
+            }
+        }
+        let first_head = if config.deep_supervision { depth.min(1) } else { depth };
+        let heads = (first_head ..= depth)
+            .map(|j| (j, Conv2D::builder(width, outputs, 1, 1).build(env)))
+            .collect();
+        let dropout = vec![None; depth + 1];
+        Self { nodes, downsample, dropout, heads }
+    }
+
+    //Adds dropout behind the encoder nodes of the depths the config lists
+    pub fn with_dropout(mut self, config: &DropoutConfig) -> Self {
+        for (i, dropout) in self.dropout.iter_mut().enumerate() {
+            *dropout = Dropout::at_level(config, i);
+        }
+        self
+    }
+
+    //Names of the trainable parameters in the order they are created in new(), see UNet::parameter_names
+    pub fn parameter_names(&self) -> Vec<String> {
+        let depth = self.nodes.len() - 1;
+        let layer = |layer: String| [format!("{layer}.filter"), format!("{layer}.bias")];
+        let node = |i: usize, j: usize| [layer(format!("node{i}_{j}.conv1")), layer(format!("node{i}_{j}.conv2"))].concat();
+        let mut names = Vec::new();
+        for i in 0 ..= depth {
+            if i > 0 && self.downsample[i - 1].is_trainable() {
+                names.extend(layer(format!("down{i}")));
+            }
+            names.extend(node(i, 0));
+        }
+        for j in 1 ..= depth {
+            for i in 0 ..= depth - j {
+                names.extend(node(i, j));
+            }
+        }
+        for (j, _) in self.heads.iter() {
+            names.extend(layer(format!("head{j}")));
+        }
+        names
+    }
+
+    //Number of outputs eval_outputs returns
+    pub fn output_count(&self) -> usize {
+        self.heads.len()
+    }
+
+    //Every head, the output first and then the deep supervision heads from the shallowest column,
+    //all cropped to the size of the output
+    pub fn eval_outputs<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> Vec<DualArray<'s>> {
+        let top = self.top_nodes(input, ctx);
+        let mut outputs = self.heads.iter().rev()
+            .map(|(j, head)| top[*j].apply(head, ctx).leaky_relu(0.01))
+            .collect::<Vec<_>>();
+        let [_, height, width, _]: [usize; 4] = outputs[0].shape().try_into().unwrap();
+        outputs[1 ..].reverse();
+        outputs.into_iter().map(|output| center_crop(output, height, width)).collect()
+    }
+
+    //Nodes (0, j) for every column j
+    fn top_nodes<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> Vec<DualArray<'s>> {
+        let depth = self.nodes.len() - 1;
+        let node = |x: DualArray<'s>, i: usize, j: usize| {
+            let (conv1, conv2) = &self.nodes[i][j];
+            let y = block(x, conv1, conv2, None, ctx);
+            match self.dropout[i].as_ref().filter(|_| j == 0) {
+                Some(dropout) => y.apply(dropout, ctx),
+                None => y,
+            }
+        };
+        let mut x: Vec<Vec<DualArray<'s>>> = Vec::with_capacity(depth + 1);
+        x.push(vec![node(input, 0, 0)]);
+        for i in 1 ..= depth {
+            let pooled = x[i - 1][0].apply(&self.downsample[i - 1], ctx);
+            x.push(vec![node(pooled, i, 0)]);
+        }
+        for j in 1 ..= depth {
+            for i in 0 ..= depth - j {
+                //Everything is brought to the size of the previous node of this depth, which is the smallest one
+                let [_, height, width, _]: [usize; 4] = x[i][j - 1].shape().try_into().unwrap();
+                let below = x[i + 1][j - 1];
+                let [_, h_below, w_below, _]: [usize; 4] = below.shape().try_into().unwrap();
+                let skip = SkipAlignment::new((height, width), (h_below, w_below));
+                let upsampled = below.upsample(skip.factor_x, skip.factor_y).crop(skip.left, skip.top, skip.right, skip.bottom);
+                let inputs = x[i].iter().rev()
+                    .map(|&earlier| center_crop(earlier, height, width))
+                    .reduce(|later, earlier| earlier.concat(later, -1))
+                    .unwrap();
+                x[i].push(node(inputs.concat(upsampled, -1), i, j));
+            }
+        }
+        x.swap_remove(0)
+    }
+}
+
+impl Module for UNetPlusPlus {
+    fn eval<'s>(&self, input: DualArray<'s>, ctx: &EvalContext) -> DualArray<'s> {
+        let top = self.top_nodes(input, ctx);
+        let (j, head) = self.heads.last().unwrap();
+        top[*j].apply(head, ctx).leaky_relu(0.01)
+    }
+}
@@ -0,0 +1,134 @@
+use descent_unet_example::augment::{Augmentation, ImagePair};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+const HEIGHT: usize = 6;
+const WIDTH: usize = 9;
+
+//Input channels are the y and x coordinate of each pixel, the target is the pixel index y * WIDTH + x,
+//so after any geometric transform the target still follows from the input
+fn ramp() -> ImagePair {
+    let pixels = (0 .. HEIGHT).flat_map(|y| (0 .. WIDTH).map(move |x| (y as f32, x as f32)));
+    ImagePair {
+        input: pixels.clone().flat_map(|(y, x)| [y, x]).collect(),
+        target: pixels.map(|(y, x)| y * WIDTH as f32 + x).collect(),
+        height: HEIGHT,
+        width: WIDTH,
+        input_channels: 2,
+        target_channels: 1,
+        target_is_label: false,
+    }
+}
+
+//Always returns the same number, so every rng.gen_range draws the same value
+struct Constant(u32);
+
+impl RngCore for Constant {
+    fn next_u32(&mut self) -> u32 {
+        self.0
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        ((self.0 as u64) << 32) | self.0 as u64
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        dest.iter_mut().for_each(|b| *b = self.0 as u8);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[test]
+fn input_and_target_get_the_same_geometric_transform() {
+    let pipeline = [
+        Augmentation::Flip { horizontal: true, vertical: true },
+        Augmentation::Rotate90,
+        Augmentation::Crop { min_scale: 0.5 },
+        Augmentation::Affine { max_rotation: 30.0, max_scale: 0.2, max_shear: 10.0, max_translation: 0.1 },
+        Augmentation::Elastic { alpha: 2.0, sigma: 1.5 },
+    ];
+    for augmentation in pipeline.iter() {
+        for seed in 0 .. 8 {
+            let mut pair = ramp();
+            augmentation.apply(&mut pair, &mut StdRng::seed_from_u64(seed));
+            assert_eq!(pair.input.len(), pair.height * pair.width * 2);
+            assert_eq!(pair.target.len(), pair.height * pair.width);
+            //Interpolation is linear, so this holds for the warps as well
+            for (input, target) in pair.input.chunks(2).zip(pair.target.iter()) {
+                let expected = input[0] * WIDTH as f32 + input[1];
+                assert!((target - expected).abs() < 1.0E-3, "{augmentation:?} seed {seed}: target {target}, input says {expected}");
+            }
+        }
+    }
+}
+
+#[test]
+fn label_targets_keep_their_labels_and_image_targets_are_interpolated() {
+    let pipeline = [
+        Augmentation::Affine { max_rotation: 30.0, max_scale: 0.2, max_shear: 10.0, max_translation: 0.1 },
+        Augmentation::Elastic { alpha: 2.0, sigma: 1.5 },
+    ];
+    let labels = [0.0, 1.0, 2.0];
+    for augmentation in pipeline.iter() {
+        let mut interpolated = false;
+        for seed in 0 .. 8 {
+            for target_is_label in [true, false] {
+                let mut pair = ramp();
+                pair.target = (0 .. HEIGHT).flat_map(|y| (0 .. WIDTH).map(move |x| ((y / 2 + x / 3) % 3) as f32)).collect();
+                pair.target_is_label = target_is_label;
+                augmentation.apply(&mut pair, &mut StdRng::seed_from_u64(seed));
+                let outside = pair.target.iter().filter(|v| !labels.contains(v)).count();
+                if target_is_label {
+                    assert_eq!(outside, 0, "{augmentation:?} seed {seed} made up labels: {:?}", pair.target);
+                } else {
+                    interpolated |= outside > 0;
+                }
+            }
+        }
+        assert!(interpolated, "{augmentation:?} never interpolated an image target");
+    }
+}
+
+#[test]
+fn rotate90_four_times_is_the_identity() {
+    //Each constant picks one of the four rotations
+    let mut rotated = Vec::new();
+    for quarter in 0 .. 4u32 {
+        let mut rng = Constant(quarter << 30);
+        let mut pair = ramp();
+        Augmentation::Rotate90.apply(&mut pair, &mut rng);
+        rotated.push(pair.clone());
+        for _ in 0 .. 3 {
+            Augmentation::Rotate90.apply(&mut pair, &mut rng);
+        }
+        assert_eq!(pair, ramp(), "Rotation {quarter}");
+    }
+    for (i, a) in rotated.iter().enumerate() {
+        for b in rotated[i + 1 ..].iter() {
+            assert_ne!(a, b, "Every rotation should be covered");
+        }
+    }
+}
+
+#[test]
+fn photometric_augmentations_leave_the_target_untouched() {
+    let pipeline = [
+        Augmentation::ColorJitter { brightness: 0.5, contrast: 0.5, hue: 0.0 },
+        Augmentation::GaussianNoise { std: 0.5 },
+    ];
+    for augmentation in pipeline.iter() {
+        for seed in 0 .. 4 {
+            let mut pair = ramp();
+            augmentation.apply(&mut pair, &mut StdRng::seed_from_u64(seed));
+            assert_eq!(pair.target, ramp().target, "{augmentation:?} seed {seed}");
+            assert_ne!(pair.input, ramp().input, "{augmentation:?} seed {seed}");
+        }
+    }
+    //Hue rotation needs rgb inputs
+    let mut pair = ImagePair { input: (0 .. 12).map(|i| i as f32 / 12.0).collect(), target: vec![1.0, 0.0, 2.0, 1.0], height: 2, width: 2, input_channels: 3, target_channels: 1, target_is_label: true };
+    Augmentation::ColorJitter { brightness: 0.0, contrast: 0.0, hue: 0.5 }.apply(&mut pair, &mut StdRng::seed_from_u64(1));
+    assert_eq!(pair.target, [1.0, 0.0, 2.0, 1.0]);
+}
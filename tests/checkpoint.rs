@@ -0,0 +1,91 @@
+use descent_unet_example::checkpoint::{Checkpoint, Tensor};
+use std::{env, fs, io::ErrorKind, path::PathBuf};
+
+mod common;
+
+fn checkpoint() -> Checkpoint {
+    let config = common::config(1, 1, 1, 2);
+    let tensors = vec![
+        Tensor { name: "conv1.filter".to_string(), shape: vec![1, 2, 3, 3, 1], values: (0 .. 18).map(|i| i as f32).collect() },
+        Tensor { name: "conv1.bias".to_string(), shape: vec![2], values: vec![0.5, -0.5] },
+        Tensor { name: "inner.conv1.filter".to_string(), shape: vec![1, 1, 1, 1, 2], values: vec![f32::MIN_POSITIVE, -3.0E38] },
+        Tensor { name: "attention.psi.bias".to_string(), shape: vec![1], values: vec![1.0E-40] },
+    ];
+    Checkpoint { config, epoch: 3, tensors }
+}
+
+fn saved(name: &str) -> (PathBuf, Vec<u8>) {
+    let path = env::temp_dir().join(name);
+    checkpoint().save(&path).unwrap();
+    let bytes = fs::read(&path).unwrap();
+    (path, bytes)
+}
+
+#[test]
+fn truncated_checkpoints_are_rejected() {
+    let (path, bytes) = saved("descent_unet_truncated.unet");
+    assert!(Checkpoint::load(&path).is_ok());
+    for len in 0 .. bytes.len() {
+        fs::write(&path, &bytes[.. len]).unwrap();
+        assert!(Checkpoint::load(&path).is_err(), "Checkpoint cut to {len} of {} bytes loaded", bytes.len());
+    }
+}
+
+#[test]
+fn lengths_beyond_the_file_are_invalid_data() {
+    let (path, bytes) = saved("descent_unet_lengths.unet");
+    //Config length right after magic and version
+    let mut corrupt = bytes.clone();
+    corrupt[12 .. 20].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&path, &corrupt).unwrap();
+    assert_eq!(Checkpoint::load(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+
+    //Tensor count after config and epoch
+    let config = u64::from_le_bytes(bytes[12 .. 20].try_into().unwrap()) as usize;
+    let count = 20 + config + 8;
+    let mut corrupt = bytes.clone();
+    corrupt[count .. count + 8].copy_from_slice(&(1u64 << 60).to_le_bytes());
+    fs::write(&path, &corrupt).unwrap();
+    assert_eq!(Checkpoint::load(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+
+    //First dim of the first tensor, the shape product overflows
+    let dim = count + 8 + 8 + "conv1.filter".len() + 8;
+    let mut corrupt = bytes.clone();
+    corrupt[dim .. dim + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    fs::write(&path, &corrupt).unwrap();
+    assert_eq!(Checkpoint::load(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+}
+
+fn assert_same_tensors(actual: &[Tensor], expected: &[Tensor]) {
+    let names = |tensors: &[Tensor]| tensors.iter().map(|tensor| tensor.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(actual), names(expected));
+    for (actual, expected) in actual.iter().zip(expected.iter()) {
+        assert_eq!(actual.shape, expected.shape, "{}", actual.name);
+        assert_eq!(actual.values, expected.values, "{}", actual.name);
+    }
+}
+
+#[test]
+fn safetensors_keep_config_epoch_and_parameter_order() {
+    let expected = checkpoint();
+    let path = env::temp_dir().join("descent_unet_roundtrip.safetensors");
+    expected.save(&path).unwrap();
+    let loaded = Checkpoint::load(&path).unwrap();
+    assert_eq!(loaded.config.to_json(), expected.config.to_json());
+    assert_eq!(loaded.epoch, expected.epoch);
+    //Not alphabetical, so the order has to come from the metadata
+    assert_same_tensors(&loaded.tensors, &expected.tensors);
+}
+
+#[test]
+fn native_and_safetensors_checkpoints_load_the_same() {
+    let native = env::temp_dir().join("descent_unet_same.unet");
+    let safetensors = env::temp_dir().join("descent_unet_same.safetensors");
+    checkpoint().save(&native).unwrap();
+    checkpoint().save(&safetensors).unwrap();
+    let native = Checkpoint::load(&native).unwrap();
+    let safetensors = Checkpoint::load(&safetensors).unwrap();
+    assert_eq!(native.config.to_json(), safetensors.config.to_json());
+    assert_eq!(native.epoch, safetensors.epoch);
+    assert_same_tensors(&native.tensors, &safetensors.tensors);
+}
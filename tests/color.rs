@@ -0,0 +1,45 @@
+use descent_unet_example::color::ColorSpace;
+use image::{DynamicImage, Rgb32FImage};
+
+//Black, white, greys, saturated primaries and secondaries and a few mixed colours
+const COLORS: [[f32; 3]; 12] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 1.0, 1.0],
+    [0.5, 0.5, 0.5],
+    [1.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 1.0, 1.0],
+    [1.0, 0.0, 1.0],
+    [0.2, 0.4, 0.6],
+    [0.9, 0.1, 0.3],
+    [0.01, 0.02, 0.005],
+];
+
+#[test]
+fn split_and_combine_roundtrip_in_every_space() {
+    let rgb = COLORS.concat();
+    for space in [ColorSpace::Rgb, ColorSpace::Lab, ColorSpace::YCbCr] {
+        let (lightness, target) = space.split(&rgb);
+        assert_eq!(lightness.len(), COLORS.len());
+        assert_eq!(target.len(), COLORS.len() * space.target_channels());
+        let combined = space.combine(&lightness, &target);
+        for (i, (actual, expected)) in combined.chunks(3).zip(COLORS.iter()).enumerate() {
+            for (a, e) in actual.iter().zip(expected.iter()) {
+                assert!((a - e).abs() < 2.0E-3, "{space:?} colour {i}: {actual:?} instead of {expected:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn rgb_lightness_matches_image_luma() {
+    let rgb = COLORS.concat();
+    let image = DynamicImage::from(Rgb32FImage::from_vec(COLORS.len() as u32, 1, rgb.clone()).unwrap());
+    let luma = image.to_luma32f().to_vec();
+    let (lightness, _) = ColorSpace::Rgb.split(&rgb);
+    for (i, (l, expected)) in lightness.iter().zip(luma.iter()).enumerate() {
+        assert!((l - expected).abs() < 1.0E-5, "colour {i}: {l} instead of {expected}");
+    }
+}
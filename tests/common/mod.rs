@@ -0,0 +1,30 @@
+//Shared by the integration tests, each of which only uses some of it
+#![allow(dead_code)]
+
+use descent::prelude::*;
+use descent_unet_example::{config::ExperimentConfig, network, reference::FeatureMap};
+
+//Runs f on a single input as a graph of its own
+pub fn run_descent(env: &mut Environment, input: &FeatureMap, f: impl for<'s> Fn(DualArray<'s>) -> DualArray<'s>) -> FeatureMap {
+    let input_param = env.static_parameter_with_data([1, input.height, input.width, input.channels], "input", &input.values);
+    let (values, shape) = network::run_once(env, &input_param, f, 0);
+    let [_, height, width, channels]: [usize; 4] = shape.try_into().unwrap();
+    FeatureMap::new(values, height, width, channels)
+}
+
+//One epoch on the capybara image in 64 pixel tiles, tests override whatever else they need
+pub fn config(inputs: usize, outputs: usize, depth: usize, width: usize) -> ExperimentConfig {
+    ExperimentConfig::from_toml(&format!(r#"
+epochs = 1
+
+[model]
+inputs = {inputs}
+outputs = {outputs}
+depth = {depth}
+width = {width}
+
+[dataset]
+images = ["images/Capybara_128px_square.jpg"]
+tile_size = 64
+"#)).unwrap()
+}
@@ -0,0 +1,89 @@
+use descent_unet_example::{config::Normalization, data::{self, PredictionInput}, npy};
+use image::{Rgb, RgbImage};
+use std::{env, fs::File, io::ErrorKind};
+use tiff::encoder::{colortype, TiffEncoder};
+
+mod common;
+
+#[test]
+fn tiff_pages_stack_into_hwc_bands() {
+    let (height, width) = (2, 3);
+    let path = env::temp_dir().join("descent_unet_bands.tif");
+    let mut encoder = TiffEncoder::new(File::create(&path).unwrap()).unwrap();
+    //One band on the first page, three on the second
+    let grey = (0 .. 6u16).map(|i| 1000 + i).collect::<Vec<_>>();
+    let rgb = (0 .. 18u8).collect::<Vec<_>>();
+    encoder.write_image::<colortype::Gray16>(width as u32, height as u32, &grey).unwrap();
+    encoder.write_image::<colortype::RGB8>(width as u32, height as u32, &rgb).unwrap();
+    drop(encoder);
+
+    let (values, h, w) = data::load_input(&path, 4).unwrap();
+    assert_eq!((h, w), (height, width));
+    let expected = (0 .. height * width)
+        .flat_map(|pixel| [grey[pixel] as f32, rgb[3 * pixel] as f32, rgb[3 * pixel + 1] as f32, rgb[3 * pixel + 2] as f32])
+        .collect::<Vec<_>>();
+    assert_eq!(values, expected);
+
+    let error = data::load_input(&path, 3).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("has 4 bands instead of 3"), "{error}");
+}
+
+#[test]
+fn npy_rasters_keep_their_hwc_layout() {
+    let path = env::temp_dir().join("descent_unet_bands.npy");
+    let values = (0 .. 2 * 3 * 5).map(|i| i as f32 - 7.5).collect::<Vec<_>>();
+    npy::write(&path, &[2, 3, 5], &values, npy::Dtype::F32).unwrap();
+    assert_eq!(data::load_input(&path, 5).unwrap(), (values, 2, 3));
+    let error = data::load_input(&path, 4).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("has 5 bands instead of 4"), "{error}");
+
+    //A single band can leave out the channel axis
+    npy::write(&path, &[3, 2], &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], npy::Dtype::U8).unwrap();
+    assert_eq!(data::load_input(&path, 1).unwrap(), (vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 3, 2));
+}
+
+#[test]
+fn channel_statistics_are_per_channel_over_all_inputs() {
+    //Two images of two pixels with two channels each, the second channel is constant
+    let inputs = [vec![1.0, 5.0, 3.0, 5.0], vec![5.0, 5.0, 7.0, 5.0]];
+    let statistics = data::channel_statistics(inputs.into_iter().map(Ok), 2).unwrap();
+    assert_eq!(statistics.mean, [4.0, 5.0]);
+    //Population std of 1, 3, 5, 7, the constant channel keeps a std of 1 so it is only shifted
+    assert!((statistics.std[0] - 5.0f32.sqrt()).abs() < 1.0E-6, "{:?}", statistics.std);
+    assert_eq!(statistics.std[1], 1.0);
+}
+
+#[test]
+fn channel_statistics_without_pixels_are_invalid_data() {
+    let error = data::channel_statistics(Vec::<std::io::Result<Vec<f32>>>::new(), 3).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    let error = data::channel_statistics([Ok(Vec::new())], 3).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn prediction_inputs_use_the_stored_normalization() {
+    let mut config = common::config(2, 1, 1, 4);
+    let path = env::temp_dir().join("descent_unet_predict.npy");
+    npy::write(&path, &[1, 2, 2], &[1.0, 10.0, 3.0, 30.0], npy::Dtype::F32).unwrap();
+    let raw = PredictionInput::load(&config, &path).unwrap();
+    assert_eq!((raw.input, raw.height, raw.width), (vec![1.0, 10.0, 3.0, 30.0], 1, 2));
+
+    config.model.normalization = Some(Normalization { mean: vec![2.0, 20.0], std: vec![1.0, 10.0] });
+    let normalized = PredictionInput::load(&config, &path).unwrap();
+    assert_eq!(normalized.input, [-1.0, -1.0, 1.0, 1.0]);
+    assert!(normalized.colorized.is_none());
+
+    //Colorization normalizes the lightness the network sees, but combines with the original one
+    let path = env::temp_dir().join("descent_unet_predict.png");
+    RgbImage::from_fn(2, 1, |x, _| Rgb([x as u8 * 255; 3])).save(&path).unwrap();
+    config.model.inputs = 1;
+    config.model.normalization = Some(Normalization { mean: vec![0.5], std: vec![0.25] });
+    config.dataset.colorize = Some(Default::default());
+    let colorized = PredictionInput::load(&config, &path).unwrap();
+    let (_, lightness) = colorized.colorized.unwrap();
+    assert_eq!(lightness, [0.0, 1.0]);
+    assert_eq!(colorized.input, [-2.0, 2.0]);
+}
@@ -0,0 +1,52 @@
+use descent_unet_example::{augment::Augmentation, config::{DropoutConfig, DropoutKind, ExperimentConfig}, seed::RunSeed, train};
+use rand::RngCore;
+
+mod common;
+
+//Two epochs of three batches with random augmentations, so that every random stream is used
+fn config() -> ExperimentConfig {
+    let mut config = common::config(1, 3, 1, 4);
+    config.seed = Some(1234);
+    config.epochs = 2;
+    config.schedule.steps_per_epoch = 3;
+    config.schedule.checkpoint_every = 2;
+    config.dataset.batch_size = 2;
+    config.augmentation = vec![
+        Augmentation::Flip { horizontal: true, vertical: true },
+        Augmentation::GaussianNoise { std: 0.01 },
+    ];
+    config
+}
+
+#[test]
+fn seed_streams_are_reproducible_and_independent() {
+    let a = RunSeed(7);
+    let b = RunSeed(7);
+    assert_eq!(a.init_rng().next_u64(), b.init_rng().next_u64());
+    assert_eq!(a.graph_rng().next_u64(), b.graph_rng().next_u64());
+    assert_ne!(a.init_rng().next_u64(), a.data_rng().next_u64());
+    assert_ne!(a.init_rng().next_u64(), RunSeed(8).init_rng().next_u64());
+}
+
+#[test]
+fn same_seed_gives_identical_losses() {
+    let config = config();
+    let dir = std::env::temp_dir().join("descent_unet_determinism");
+    let first = train::train(&config, &dir.join("first"));
+    let second = train::train(&config, &dir.join("second"));
+    assert_eq!(first, second);
+
+    let other = train::train(&ExperimentConfig { seed: Some(4321), ..config }, &dir.join("other"));
+    assert_ne!(first, other);
+}
+
+//The dropout masks come from the graph seed, so they repeat with the run seed as well
+#[test]
+fn same_seed_gives_identical_losses_with_dropout() {
+    let mut config = config();
+    config.model.dropout = Some(DropoutConfig { kind: DropoutKind::Spatial, rate: 0.2, levels: vec![0, 1] });
+    let dir = std::env::temp_dir().join("descent_unet_determinism_dropout");
+    let first = train::train(&config, &dir.join("first"));
+    let second = train::train(&config, &dir.join("second"));
+    assert_eq!(first, second);
+}
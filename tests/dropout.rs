@@ -0,0 +1,60 @@
+use descent::{module::*, prelude::*};
+use descent_unet_example::{config::{DropoutConfig, DropoutKind}, dropout::Dropout, network};
+
+mod common;
+
+const SHAPE: [usize; 4] = [2, 16, 16, 8];
+
+//Runs dropout on ones with the given graph seed
+fn run(dropout: Dropout, training: bool, seed: u32) -> Vec<f32> {
+    let mut env = Environment::new();
+    let input = env.static_parameter_with_data(SHAPE, "input", &vec![1.0; SHAPE.iter().product()]);
+    network::run_once(&mut env, &input, |x| if training { dropout.train(x) } else { dropout.test(x) }, seed).0
+}
+
+#[test]
+fn dropout_is_identity_under_test() {
+    for kind in [DropoutKind::Element, DropoutKind::Spatial] {
+        assert!(run(Dropout::new(0.5, kind), false, 1).iter().all(|&v| v == 1.0));
+    }
+}
+
+#[test]
+fn element_dropout_drops_about_rate_and_rescales_survivors() {
+    let y = run(Dropout::new(0.25, DropoutKind::Element), true, 1);
+    let dropped = y.iter().filter(|&&v| v == 0.0).count() as f32 / y.len() as f32;
+    assert!((dropped - 0.25).abs() < 0.05, "{dropped} dropped");
+    assert!(y.iter().all(|&v| v == 0.0 || (v - 1.0 / 0.75).abs() < 1e-5));
+}
+
+#[test]
+fn spatial_dropout_drops_whole_channels() {
+    let y = run(Dropout::new(0.5, DropoutKind::Spatial), true, 1);
+    let [n, h, w, c] = SHAPE;
+    let mut dropped = 0;
+    for sample in 0 .. n {
+        for channel in 0 .. c {
+            let map = (0 .. h * w).map(|i| y[(sample * h * w + i) * c + channel]).collect::<Vec<_>>();
+            assert!(map.iter().all(|&v| v == map[0]), "channel {channel} of sample {sample} is partly dropped");
+            assert!(map[0] == 0.0 || (map[0] - 1.0 / 0.5).abs() < 1e-5, "channel {channel} of sample {sample} is {}", map[0]);
+            dropped += (map[0] == 0.0) as usize;
+        }
+    }
+    assert!(dropped > 0 && dropped < n * c, "{dropped} of {} channels dropped", n * c);
+}
+
+#[test]
+fn dropout_without_levels_is_rejected() {
+    let mut config = common::config(1, 1, 2, 4);
+    for (levels, valid) in [(vec![0, 2], true), (vec![], false), (vec![3], false)] {
+        config.model.dropout = Some(DropoutConfig { kind: DropoutKind::default(), rate: 0.5, levels });
+        assert_eq!(config.validate().is_ok(), valid, "{:?}", config.model.dropout);
+    }
+}
+
+#[test]
+fn dropout_mask_follows_the_graph_seed() {
+    let dropout = Dropout::new(0.5, DropoutKind::Element);
+    assert_eq!(run(dropout, true, 7), run(dropout, true, 7));
+    assert_ne!(run(dropout, true, 7), run(dropout, true, 8));
+}
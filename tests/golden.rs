@@ -0,0 +1,100 @@
+use descent::{module::*, prelude::*};
+use descent_unet_example::{checkpoint::Checkpoint, network::Network, npy, reference::{self, FeatureMap}, unet::WithAttentionMaps};
+use std::{env, path::PathBuf};
+
+mod common;
+use common::run_descent;
+
+//Checked-in checkpoints with seeded random weights, their inputs and the outputs the forward pass
+//has to keep producing. After an intended change to the forward pass, run
+//  UPDATE_GOLDEN=1 cargo test --test golden
+//to rewrite the expected outputs from descent
+const CASES: [&str; 9] = [
+    "unet_depth0_width4",
+    "unet_depth1_width4",
+    "unet_depth2_width2",
+    "resunet_depth1_width4",
+    "attunet_depth2_width2",
+    "unetpp_depth2_width2",
+    "unet_avgpool3_depth1_width4",
+    "unet_strided3_depth1_width4",
+    "fcn_depth2_width4",
+];
+
+//Upsampling factors (x, y) and crop margins (left, top, right, bottom) applied to ops.input.npy
+const UPSAMPLE: (usize, usize) = (3, 2);
+const CROP: (usize, usize, usize, usize) = (1, 2, 0, 3);
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/golden").join(name)
+}
+
+fn load(name: &str) -> FeatureMap {
+    let (shape, values) = npy::read(fixture(name)).unwrap_or_else(|e| panic!("Could not read {name}: {e}"));
+    let [height, width, channels]: [usize; 3] = shape.try_into().unwrap();
+    FeatureMap::new(values, height, width, channels)
+}
+
+//Either compares against the stored output or replaces it when UPDATE_GOLDEN is set
+fn check(actual: &FeatureMap, name: &str) {
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        npy::write(fixture(name), &[actual.height, actual.width, actual.channels], &actual.values, npy::Dtype::F32).unwrap();
+        return;
+    }
+    let expected = load(name);
+    assert_eq!((actual.height, actual.width, actual.channels), (expected.height, expected.width, expected.channels), "{name} changed shape");
+    //Loose enough for a different summation order on the GPU
+    for (i, (a, e)) in actual.values.iter().zip(expected.values.iter()).enumerate() {
+        assert!((a - e).abs() <= 1e-4 + 1e-3 * e.abs(), "{name} drifted at {i}: {a} instead of {e}");
+    }
+}
+
+#[test]
+fn descent_forward_pass_matches_golden_outputs() {
+    for case in CASES {
+        let checkpoint = Checkpoint::load(fixture(&format!("{case}.unet"))).unwrap();
+        let input = load(&format!("{case}.input.npy"));
+        let mut env = Environment::new();
+        let network = Network::new(&mut env, &checkpoint.config.model);
+        let parameters = network.parameters(&mut env, [1, input.height, input.width, input.channels]);
+        checkpoint.upload(&mut env, &network.parameter_names(), &parameters).unwrap();
+        let output = run_descent(&mut env, &input, |x| network.test(x));
+        check(&output, &format!("{case}.output.npy"));
+        //Cases with attention gates also store the prediction with the maps behind it, like predict --attention-maps
+        if let Network::UNet(unet) = &network {
+            if unet.attention_levels() > 0 {
+                let output = run_descent(&mut env, &input, |x| WithAttentionMaps(unet).test(x));
+                check(&output, &format!("{case}.attention.npy"));
+            }
+        }
+    }
+}
+
+#[test]
+fn descent_crop_and_upsample_match_golden_outputs() {
+    let input = load("ops.input.npy");
+    let mut env = Environment::new();
+    let (left, top, right, bottom) = CROP;
+    check(&run_descent(&mut env, &input, |x| x.upsample(UPSAMPLE.0, UPSAMPLE.1)), "ops.upsample.npy");
+    check(&run_descent(&mut env, &input, |x| x.crop(left, top, right, bottom)), "ops.crop.npy");
+}
+
+//The CPU reference has to agree with the same fixtures, which also keeps it honest as a check for descent
+#[test]
+fn reference_forward_pass_matches_golden_outputs() {
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        return;
+    }
+    for case in CASES {
+        let checkpoint = Checkpoint::load(fixture(&format!("{case}.unet"))).unwrap();
+        let (output, maps) = reference::forward_with_attention(&checkpoint, load(&format!("{case}.input.npy"))).unwrap();
+        check(&output, &format!("{case}.output.npy"));
+        if !maps.is_empty() {
+            check(&maps.iter().fold(output, |y, map| reference::concat(&y, map)), &format!("{case}.attention.npy"));
+        }
+    }
+    let input = load("ops.input.npy");
+    let (left, top, right, bottom) = CROP;
+    check(&reference::upsample(&input, UPSAMPLE.0, UPSAMPLE.1), "ops.upsample.npy");
+    check(&reference::crop(&input, left, top, right, bottom), "ops.crop.npy");
+}
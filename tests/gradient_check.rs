@@ -0,0 +1,131 @@
+use descent::prelude::*;
+use descent_unet_example::{config::{Block, Downsampling, UNetConfig}, fcn::FCN, gradcheck::{GradientCheck, GradientReport}, network::{DeepSupervision, Network}, unet::UNet, unet_plus_plus::UNetPlusPlus};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::slice;
+
+fn random_parameter(env: &mut Environment, shape: [usize; 4], name: &str, rng: &mut StdRng) -> Parameter {
+    let values = (0 .. shape.iter().product::<usize>()).map(|_| rng.gen_range(-1.0 .. 1.0)).collect::<Vec<f32>>();
+    env.static_parameter_with_data(shape, name, &values)
+}
+
+fn assert_passes(report: &GradientReport, what: &str) {
+    let failures = report.failures();
+    assert!(failures.is_empty(), "{what}: {} of {} gradients are off, e.g. {:?}", failures.len(), report.samples.len(), failures[0]);
+}
+
+#[test]
+fn crop_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut env = Environment::new();
+    //Uneven margins on every side
+    let x = random_parameter(&mut env, [2, 7, 6, 3], "x", &mut rng);
+    let report = GradientCheck::default().run(&mut env, slice::from_ref(&x), |scope| scope.parameter(&x).crop(1, 2, 0, 3), &mut rng);
+    assert_passes(&report, "crop");
+}
+
+#[test]
+fn upsample_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let mut env = Environment::new();
+    let x = random_parameter(&mut env, [1, 4, 3, 2], "x", &mut rng);
+    //Every input element collects the gradient of a 2x3 block
+    let check = GradientCheck { samples: 24, ..Default::default() };
+    let report = check.run(&mut env, slice::from_ref(&x), |scope| scope.parameter(&x).upsample(2, 3), &mut rng);
+    assert_passes(&report, "upsample");
+}
+
+#[test]
+fn concat_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut env = Environment::new();
+    let a = random_parameter(&mut env, [1, 5, 5, 2], "a", &mut rng);
+    let b = random_parameter(&mut env, [1, 5, 5, 3], "b", &mut rng);
+    let report = GradientCheck::default().run(&mut env, &[a.clone(), b.clone()], |scope| scope.parameter(&a).concat(scope.parameter(&b), -1), &mut rng);
+    assert_passes(&report, "concat");
+}
+
+//Leaky relu and max pooling have kinks, a perturbation that crosses one gives a meaningless
+//difference quotient, so a few samples are allowed to be off
+fn assert_mostly_passes(report: &GradientReport, what: &str) {
+    let failures = report.failures();
+    assert!(failures.len() * 50 <= report.samples.len(), "{what}: {} of {} gradients are off, e.g. {:?}", failures.len(), report.samples.len(), failures.first());
+}
+
+#[test]
+fn unet_block_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(4);
+    let mut env = Environment::new();
+    let unet = UNet::new(&mut env, 1, 2, 0, 4, 3);
+    let report = GradientCheck::default().run_module(&mut env, &unet, [1, 12, 12, 1], &mut rng);
+    assert_mostly_passes(&report, "unet depth 0");
+}
+
+#[test]
+fn nested_unet_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let mut env = Environment::new();
+    //Pooling, upsampling, cropping and concatenation all sit between input and output
+    let unet = UNet::new(&mut env, 2, 2, 1, 4, 3);
+    let report = GradientCheck::default().run_module(&mut env, &unet, [1, 32, 32, 2], &mut rng);
+    assert_mostly_passes(&report, "unet depth 1");
+}
+
+#[test]
+fn residual_unet_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(7);
+    let mut env = Environment::new();
+    let unet = UNet::with_config(&mut env, 2, 2, 1, 4, 3, UNetConfig { block: Block::Residual, ..Default::default() });
+    let report = GradientCheck::default().run_module(&mut env, &unet, [1, 32, 32, 2], &mut rng);
+    assert_mostly_passes(&report, "residual unet");
+}
+
+#[test]
+fn attention_unet_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(8);
+    let mut env = Environment::new();
+    let unet = UNet::with_config(&mut env, 2, 2, 1, 4, 3, UNetConfig { attention: true, ..Default::default() });
+    let report = GradientCheck::default().run_module(&mut env, &unet, [1, 32, 32, 2], &mut rng);
+    assert_mostly_passes(&report, "attention unet");
+}
+
+#[test]
+fn downsampling_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(11);
+    //Average pooling reshapes the cropped input into its windows, the strided conv is a layer of its own
+    for downsampling in [Downsampling::Average, Downsampling::StridedConv] {
+        let mut env = Environment::new();
+        let unet = UNet::with_config(&mut env, 2, 2, 1, 4, 3, UNetConfig { downsampling, pool_size: 3, ..Default::default() });
+        let report = GradientCheck::default().run_module(&mut env, &unet, [1, 35, 35, 2], &mut rng);
+        assert_mostly_passes(&report, &format!("{downsampling:?} downsampling"));
+    }
+}
+
+#[test]
+fn deep_supervision_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(10);
+    let mut env = Environment::new();
+    //The prediction and the auxiliary heads of both inner levels, stacked like in training
+    let network = Network::UNet(UNet::with_config(&mut env, 1, 2, 2, 2, 3, UNetConfig { deep_supervision: true, ..Default::default() }));
+    assert_eq!(network.output_count(), 3);
+    let report = GradientCheck::default().run_module(&mut env, &DeepSupervision(&network), [1, 64, 64, 1], &mut rng);
+    assert_mostly_passes(&report, "deep supervision");
+}
+
+#[test]
+fn unet_plus_plus_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(9);
+    let mut env = Environment::new();
+    //Depth 2 has a node that reads two earlier nodes of its row
+    let unet = UNetPlusPlus::new(&mut env, 1, 2, 2, 2, 3, UNetConfig::default());
+    let report = GradientCheck::default().run_module(&mut env, &unet, [1, 44, 44, 1], &mut rng);
+    assert_mostly_passes(&report, "unet++");
+}
+
+#[test]
+fn fcn_gradients_match_finite_differences() {
+    let mut rng = StdRng::seed_from_u64(6);
+    let mut env = Environment::new();
+    let fcn = FCN::new(&mut env, 1, 2, 1, 4, 3);
+    let report = GradientCheck::default().run_module(&mut env, &fcn, [1, 10, 10, 1], &mut rng);
+    assert_mostly_passes(&report, "fcn");
+}
@@ -0,0 +1,96 @@
+use descent_unet_example::import::{import_safetensors, NameMap};
+use safetensors::{tensor::{Dtype, TensorView}, serialize_to_file};
+use std::{collections::BTreeMap, env, path::PathBuf};
+
+//Writes the tensors as a safetensors file, values given as raw little endian bytes
+fn source(file: &str, tensors: &[(&str, Dtype, Vec<usize>, Vec<u8>)]) -> PathBuf {
+    let path = env::temp_dir().join(file);
+    let views = tensors.iter().map(|(name, dtype, shape, bytes)| (*name, TensorView::new(*dtype, shape.clone(), bytes).unwrap()));
+    serialize_to_file(views, &None, &path).unwrap();
+    path
+}
+
+fn counting(len: usize) -> Vec<u8> {
+    (0 .. len).flat_map(|i| (i as f32).to_le_bytes()).collect()
+}
+
+#[test]
+fn non_square_filters_are_transposed_to_descent_layout() {
+    let (outputs, inputs, kh, kw) = (2, 3, 2, 4);
+    let path = source("descent_unet_import_filter.safetensors", &[("conv1.weight", Dtype::F32, vec![outputs, inputs, kh, kw], counting(outputs * inputs * kh * kw))]);
+    let import = import_safetensors(&path, &NameMap::default(), &["conv1.filter".to_string()], &[vec![1, outputs, kh, kw, inputs]]).unwrap();
+    let values = &import.tensors[0].values;
+    for o in 0 .. outputs {
+        for i in 0 .. inputs {
+            for y in 0 .. kh {
+                for x in 0 .. kw {
+                    assert_eq!(values[((o * kh + y) * kw + x) * inputs + i], (((o * inputs + i) * kh + y) * kw + x) as f32, "o {o} i {i} y {y} x {x}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn grouped_filters_keep_outputs_within_their_group() {
+    let (groups, outputs, inputs, kh, kw) = (2, 2, 3, 3, 1);
+    let len = groups * outputs * inputs * kh * kw;
+    let path = source("descent_unet_import_groups.safetensors", &[("conv.weight", Dtype::F32, vec![groups * outputs, inputs, kh, kw], counting(len))]);
+    let import = import_safetensors(&path, &NameMap::default(), &["conv.filter".to_string()], &[vec![groups, outputs, kh, kw, inputs]]).unwrap();
+    let values = &import.tensors[0].values;
+    for g in 0 .. groups {
+        for o in 0 .. outputs {
+            for i in 0 .. inputs {
+                for y in 0 .. kh {
+                    let source = (((g * outputs + o) * inputs + i) * kh + y) * kw;
+                    assert_eq!(values[(((g * outputs + o) * kh + y) * kw) * inputs + i], source as f32, "g {g} o {o} i {i} y {y}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn f16_and_bf16_special_values_convert_exactly() {
+    let half = |bits: &[u16]| bits.iter().flat_map(|b| b.to_le_bytes()).collect::<Vec<_>>();
+    //Smallest and largest subnormal, 1, the largest normal, infinities and NaN
+    let f16 = half(&[0x0001, 0x83ff, 0x3c00, 0x7bff, 0x7c00, 0xfc00, 0x7e00]);
+    let bf16 = half(&[0x0001, 0x3f80, 0xff80, 0x7fc0]);
+    let path = source("descent_unet_import_f16.safetensors", &[
+        ("a.bias", Dtype::F16, vec![7], f16),
+        ("b.bias", Dtype::BF16, vec![4], bf16),
+    ]);
+    let names = ["a.bias".to_string(), "b.bias".to_string()];
+    let import = import_safetensors(&path, &NameMap::default(), &names, &[vec![7], vec![4]]).unwrap();
+    let a = &import.tensors[0].values;
+    assert_eq!(a[.. 6], [2.0f32.powi(-24), -1023.0 * 2.0f32.powi(-24), 1.0, 65504.0, f32::INFINITY, f32::NEG_INFINITY]);
+    assert!(a[6].is_nan());
+    let b = &import.tensors[1].values;
+    assert_eq!(b[.. 3], [f32::from_bits(0x10000), 1.0, f32::NEG_INFINITY]);
+    assert!(b[3].is_nan());
+}
+
+#[test]
+fn name_map_reports_unmatched_and_missing_tensors() {
+    let map = NameMap { prefixes: BTreeMap::from([
+        ("enc.".to_string(), "conv1.".to_string()),
+        ("enc.shortcut.".to_string(), "encoder_shortcut.".to_string()),
+    ]) };
+    //Longest prefix wins, .weight becomes .filter
+    assert_eq!(map.map("enc.shortcut.weight"), "encoder_shortcut.filter");
+    assert_eq!(map.map("enc.bias"), "conv1.bias");
+    assert_eq!(map.map("head.weight"), "head.filter");
+
+    let path = source("descent_unet_import_names.safetensors", &[
+        ("enc.weight", Dtype::F32, vec![2, 1, 1, 1], counting(2)),
+        ("enc.bias", Dtype::F32, vec![2], counting(2)),
+        ("head.weight", Dtype::F32, vec![1, 2, 1, 1], counting(2)),
+    ]);
+    let names = ["conv1.filter", "conv1.bias", "conv2.filter", "conv2.bias"].map(String::from);
+    let shapes = [vec![1, 2, 1, 1, 1], vec![2], vec![1, 2, 3, 3, 2], vec![2]];
+    let import = import_safetensors(&path, &map, &names, &shapes).unwrap();
+    let imported = import.tensors.iter().map(|tensor| tensor.name.as_str()).collect::<Vec<_>>();
+    assert_eq!(imported, ["conv1.filter", "conv1.bias"]);
+    assert_eq!(import.unmatched, ["head.weight (as head.filter)"]);
+    assert_eq!(import.missing, ["conv2.filter", "conv2.bias"]);
+}
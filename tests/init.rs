@@ -0,0 +1,107 @@
+use descent::prelude::*;
+use descent_unet_example::{config::{Architecture, Downsampling}, init::{self, Distribution, FanMode, InitScheme, LayerKind}, network::Network};
+use rand::{rngs::StdRng, SeedableRng};
+
+mod common;
+
+//[groups, outputs, kernel height, kernel width, inputs]: fan in 3 * 3 * 32, fan out 3 * 3 * 64
+const SHAPE: [usize; 5] = [1, 64, 3, 3, 32];
+
+fn variance(values: &[f32]) -> f32 {
+    let mean = values.iter().sum::<f32>() / values.len() as f32;
+    values.iter().map(|v| (v - mean) * (v - mean)).sum::<f32>() / values.len() as f32
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| a * b).sum()
+}
+
+#[test]
+fn he_and_xavier_variance_follows_the_fans() {
+    let (fan_in, fan_out) = (3.0 * 3.0 * 32.0, 3.0 * 3.0 * 64.0);
+    let mut rng = StdRng::seed_from_u64(1);
+    for distribution in [Distribution::Normal, Distribution::Uniform] {
+        let schemes = [
+            (InitScheme::He { mode: FanMode::FanIn, distribution }, 2.0 / fan_in),
+            (InitScheme::He { mode: FanMode::FanOut, distribution }, 2.0 / fan_out),
+            (InitScheme::Xavier { distribution }, 2.0 / (fan_in + fan_out)),
+        ];
+        for (scheme, expected) in schemes {
+            let values = init::filter_values(&SHAPE, scheme, &mut rng);
+            assert_eq!(values.len(), SHAPE.iter().product::<usize>());
+            let variance = variance(&values);
+            assert!((variance / expected - 1.0).abs() < 0.05, "{scheme:?}: variance {variance}, expected {expected}");
+        }
+    }
+}
+
+#[test]
+fn orthogonal_rows_are_orthonormal() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for (rows, cols) in [(4, 27), (9, 9), (1, 5)] {
+        let matrix = init::orthogonal(rows, cols, &mut rng);
+        assert_eq!(matrix.len(), rows * cols);
+        for i in 0 .. rows {
+            for j in 0 .. rows {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                let product = dot(&matrix[i * cols .. (i + 1) * cols], &matrix[j * cols .. (j + 1) * cols]);
+                assert!((product - expected).abs() < 1.0E-4, "{rows}x{cols}: rows {i} and {j} give {product}");
+            }
+        }
+    }
+    //Filters get one matrix per group with rows of length kh * kw * inputs, scaled by the gain
+    let values = init::filter_values(&[2, 3, 3, 3, 2], InitScheme::Orthogonal { gain: 2.0 }, &mut rng);
+    for group in values.chunks(3 * 18) {
+        for (i, a) in group.chunks(18).enumerate() {
+            for (j, b) in group.chunks(18).enumerate() {
+                let expected = if i == j { 4.0 } else { 0.0 };
+                assert!((dot(a, b) - expected).abs() < 1.0E-3, "rows {i} and {j} give {}", dot(a, b));
+            }
+        }
+    }
+}
+
+#[test]
+fn layer_kinds_follow_the_architecture() {
+    let mut config = common::config(1, 1, 2, 4).model;
+    config.unet.downsampling = Downsampling::StridedConv;
+    let cases = [
+        (Architecture::Unet, vec![
+            ("conv1.filter", LayerKind::Encoder),
+            ("downsample.filter", LayerKind::Encoder),
+            ("inner.conv2.bias", LayerKind::Encoder),
+            ("inner.downsample.bias", LayerKind::Encoder),
+            ("inner.conv3.filter", LayerKind::Decoder),
+            ("inner.conv4.filter", LayerKind::Decoder),
+            ("conv3.bias", LayerKind::Decoder),
+            ("inner.auxiliary.filter", LayerKind::Output),
+            ("conv4.filter", LayerKind::Output),
+        ]),
+        (Architecture::UnetPlusPlus, vec![
+            ("node0_0.conv1.filter", LayerKind::Encoder),
+            ("node2_0.conv2.bias", LayerKind::Encoder),
+            ("down1.filter", LayerKind::Encoder),
+            ("node0_1.conv1.filter", LayerKind::Decoder),
+            ("node1_1.conv2.bias", LayerKind::Decoder),
+            ("head1.filter", LayerKind::Output),
+            ("head2.bias", LayerKind::Output),
+        ]),
+        (Architecture::Fcn, vec![
+            ("convs.0.filter", LayerKind::Encoder),
+            ("convs.1.bias", LayerKind::Encoder),
+            ("convs.3.filter", LayerKind::Output),
+        ]),
+    ];
+    let mut env = Environment::new();
+    for (architecture, kinds) in cases {
+        config.architecture = architecture;
+        //Auxiliary UNet heads are outputs as well, the FCN has none
+        config.unet.deep_supervision = architecture != Architecture::Fcn;
+        let network = Network::new(&mut env, &config);
+        let names = network.parameter_names();
+        for (name, kind) in kinds {
+            assert!(names.iter().any(|n| n == name), "{architecture:?} has no {name}: {names:?}");
+            assert_eq!(network.layer_kind(name), kind, "{architecture:?} {name}");
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use descent_unet_example::{color::ColorSpace, config::ExperimentConfig, loader::Loader, seed::RunSeed};
+use image::{Rgb, RgbImage};
+use std::{env, path::PathBuf};
+
+mod common;
+
+//16x16 tiles of the given image, of which the network predicts the central 8x8
+fn config(image: impl Into<PathBuf>) -> ExperimentConfig {
+    let mut config = common::config(1, 3, 0, 2);
+    config.dataset.images = vec![image.into()];
+    config.dataset.tile_size = 16;
+    config
+}
+
+fn loader(config: &ExperimentConfig) -> Loader {
+    Loader::new(&config.dataset, &config.model, &config.augmentation, (4, 4, 8, 8), RunSeed(1), 4)
+}
+
+#[test]
+#[should_panic(expected = "Could not open image")]
+fn worker_panics_reach_the_training_loop() {
+    let mut config = config("images/does_not_exist.png");
+    config.dataset.workers = 2;
+    loader(&config).next();
+}
+
+#[test]
+#[should_panic(expected = "(8x8) is smaller than a tile")]
+fn colorize_images_smaller_than_a_tile_are_rejected() {
+    let path = env::temp_dir().join("descent_unet_small_colorize.png");
+    RgbImage::from_fn(8, 8, |x, y| Rgb([x as u8 * 30, y as u8 * 30, 128])).save(&path).unwrap();
+    let mut config = config(path);
+    config.dataset.colorize = Some(ColorSpace::Rgb);
+    loader(&config).next();
+}
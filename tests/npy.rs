@@ -0,0 +1,128 @@
+use descent_unet_example::{checkpoint::Tensor, npy::{self, Dtype}};
+use std::{env, io::ErrorKind};
+
+fn roundtrip(shape: &[usize], values: &[f32], dtype: Dtype) -> (Vec<usize>, Vec<f32>) {
+    let mut bytes = Vec::new();
+    npy::write_to(&mut bytes, shape, values, dtype).unwrap();
+    npy::read_from(&mut bytes.as_slice()).unwrap()
+}
+
+fn header(bytes: &[u8]) -> &str {
+    let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    std::str::from_utf8(&bytes[10 .. 10 + len]).unwrap()
+}
+
+#[test]
+fn f32_arrays_roundtrip() {
+    let values = [0.0, -1.5, 3.25, f32::MAX, f32::MIN_POSITIVE, 1.0E-40];
+    assert_eq!(roundtrip(&[2, 3], &values, Dtype::F32), (vec![2, 3], values.to_vec()));
+    assert_eq!(roundtrip(&[6], &values, Dtype::F32), (vec![6], values.to_vec()));
+}
+
+#[test]
+fn f16_arrays_roundtrip_and_round_to_nearest_even() {
+    //Exactly representable, including the largest normal, a subnormal and infinity
+    let exact = [0.0, -2.0, 0.5, 65504.0, 2.0f32.powi(-24), -3.0 * 2.0f32.powi(-20), f32::INFINITY];
+    assert_eq!(roundtrip(&[7], &exact, Dtype::F16), (vec![7], exact.to_vec()));
+
+    let (_, values) = roundtrip(&[5], &[1.0 + 2.0f32.powi(-11), 1.0 + 3.0 * 2.0f32.powi(-11), 65520.0, 2.0f32.powi(-26), f32::NAN], Dtype::F16);
+    assert_eq!(values[.. 4], [1.0, 1.0 + 2.0f32.powi(-9), f32::INFINITY, 0.0]);
+    assert!(values[4].is_nan());
+
+    let mut bytes = Vec::new();
+    npy::write_to(&mut bytes, &[1], &[1.0], Dtype::F16).unwrap();
+    assert!(header(&bytes).contains("'descr': '<f2'"));
+    assert_eq!(bytes[bytes.len() - 2 ..], [0x00, 0x3c]);
+}
+
+#[test]
+fn integer_dtypes_round_and_clamp() {
+    assert_eq!(roundtrip(&[4], &[-3.0, 0.4, 127.6, 300.0], Dtype::U8).1, [0.0, 0.0, 128.0, 255.0]);
+    assert_eq!(roundtrip(&[3], &[-3.4, 0.5, 1.0E6], Dtype::I64).1, [-3.0, 1.0, 1.0E6]);
+}
+
+#[test]
+fn data_starts_at_a_multiple_of_64_bytes() {
+    for shape in [vec![1], vec![3, 4], vec![1, 2, 3, 4, 5, 6, 7], vec![123456789, 0, 1000]] {
+        let mut bytes = Vec::new();
+        npy::write_to(&mut bytes, &shape, &vec![0.0; shape.iter().product()], Dtype::F32).unwrap();
+        let header = header(&bytes);
+        assert_eq!((10 + header.len()) % 64, 0, "{header:?}");
+        assert!(header.ends_with('\n'));
+    }
+}
+
+#[test]
+fn fortran_order_is_rejected() {
+    let mut bytes = Vec::new();
+    npy::write_to(&mut bytes, &[2, 2], &[1.0, 2.0, 3.0, 4.0], Dtype::F32).unwrap();
+    let start = header(&bytes).find("False").unwrap() + 10;
+    bytes[start .. start + 5].copy_from_slice(b"True ");
+    let error = npy::read_from(&mut bytes.as_slice()).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(error.to_string().contains("C order"));
+}
+
+#[test]
+fn malformed_headers_are_invalid_data() {
+    let mut bytes = Vec::new();
+    npy::write_to(&mut bytes, &[2, 2], &[1.0, 2.0, 3.0, 4.0], Dtype::F32).unwrap();
+    let valid = header(&bytes).to_string();
+    let cases = [
+        valid.replace("(2, 2)", ")"),
+        valid.replace("(2, 2)", "(2, 2"),
+        valid.replace("(2, 2)", "[2, 2]"),
+        valid.replace("(2, 2)", "(2, x)"),
+        valid.replace("<f4", "<é4"),
+        valid.replace("<f4", "<f"),
+        valid.replace("<f4", ">f4"),
+        valid.replace("'shape'", "'shapes'"),
+    ];
+    for text in cases {
+        let mut corrupt = bytes[.. 8].to_vec();
+        corrupt.extend((text.len() as u16).to_le_bytes());
+        corrupt.extend(text.as_bytes());
+        corrupt.extend(&bytes[10 + valid.len() ..]);
+        let error = npy::read_from(&mut corrupt.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData, "{text:?}");
+    }
+}
+
+#[test]
+fn lengths_beyond_the_data_are_invalid() {
+    let mut bytes = Vec::new();
+    npy::write_to(&mut bytes, &[2, 2], &[1.0, 2.0, 3.0, 4.0], Dtype::F32).unwrap();
+    for len in 0 .. bytes.len() {
+        assert!(npy::read_from(&mut &bytes[.. len]).is_err(), "Array cut to {len} bytes was read");
+    }
+    //Header length claims more than there is
+    let mut corrupt = bytes.clone();
+    corrupt[8 .. 10].copy_from_slice(&u16::MAX.to_le_bytes());
+    assert_eq!(npy::read_from(&mut corrupt.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+    //A shape whose byte size overflows
+    let mut big = Vec::new();
+    npy::write_to(&mut big, &[0, 0], &[], Dtype::F32).unwrap();
+    let text = header(&big).replace("(0, 0)", &format!("({}, 8)", usize::MAX / 4));
+    let mut corrupt = big[.. 8].to_vec();
+    corrupt.extend((text.len() as u16).to_le_bytes());
+    corrupt.extend(text.as_bytes());
+    assert_eq!(npy::read_from(&mut corrupt.as_slice()).unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn npz_archives_keep_every_tensor_by_name() {
+    let tensors = vec![
+        Tensor { name: "conv1.filter".to_string(), shape: vec![1, 2, 3, 3, 1], values: (0 .. 18).map(|i| i as f32).collect() },
+        Tensor { name: "conv1.bias".to_string(), shape: vec![2], values: vec![0.5, -0.5] },
+        Tensor { name: "inner.conv4.bias".to_string(), shape: vec![1], values: vec![2.0] },
+    ];
+    let path = env::temp_dir().join("descent_unet_parameters.npz");
+    npy::write_npz(&path, &tensors, Dtype::F32).unwrap();
+    let read = npy::read_npz(&path).unwrap();
+    assert_eq!(read.len(), tensors.len());
+    for (read, tensor) in read.iter().zip(tensors.iter()) {
+        assert_eq!(read.name, tensor.name);
+        assert_eq!(read.shape, tensor.shape);
+        assert_eq!(read.values, tensor.values);
+    }
+}
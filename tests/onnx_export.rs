@@ -0,0 +1,219 @@
+use descent_unet_example::{checkpoint::{Checkpoint, Tensor}, config::{Architecture, Block, Downsampling, Normalization, UNetConfig}, onnx::{self, GraphProto}};
+use std::env;
+
+mod common;
+
+//Filter values count up so that the transpose can be checked
+fn push_conv(tensors: &mut Vec<Tensor>, name: &str, inputs: usize, outputs: usize, kernelsize: usize) {
+    let shape = vec![1, outputs, kernelsize, kernelsize, inputs];
+    let values = (0 .. shape.iter().product::<usize>()).map(|i| i as f32).collect();
+    tensors.push(Tensor { name: format!("{name}.filter"), shape, values });
+    tensors.push(Tensor { name: format!("{name}.bias"), shape: vec![outputs], values: vec![0.5; outputs] });
+}
+
+//Same layers as UNet::with_config
+fn push_unet(tensors: &mut Vec<Tensor>, prefix: &str, inputs: usize, outputs: usize, depth: usize, width: usize, unet: UNetConfig) {
+    let residual = unet.block == Block::Residual;
+    push_conv(tensors, &format!("{prefix}conv1"), inputs, width, 3);
+    push_conv(tensors, &format!("{prefix}conv2"), width, width, 3);
+    if residual {
+        push_conv(tensors, &format!("{prefix}encoder_shortcut"), inputs, width, 1);
+    }
+    if depth > 0 {
+        if unet.downsampling == Downsampling::StridedConv {
+            push_conv(tensors, &format!("{prefix}downsample"), width, width, unet.pool_size);
+        }
+        push_unet(tensors, &format!("{prefix}inner."), width, width * 2, depth - 1, width * 2, unet);
+        if unet.attention {
+            push_conv(tensors, &format!("{prefix}attention.skip"), width, width, 1);
+            push_conv(tensors, &format!("{prefix}attention.gate"), width * 2, width, 1);
+            push_conv(tensors, &format!("{prefix}attention.psi"), width, 1, 1);
+        }
+    }
+    let decoder_inputs = if depth > 0 { width * 3 } else { width };
+    push_conv(tensors, &format!("{prefix}conv3"), decoder_inputs, width, 3);
+    push_conv(tensors, &format!("{prefix}conv4"), width, outputs, 3);
+    if residual {
+        push_conv(tensors, &format!("{prefix}decoder_shortcut"), decoder_inputs, outputs, 1);
+    }
+}
+
+fn roundtrip(checkpoint: &Checkpoint, name: &str) -> GraphProto {
+    let path = env::temp_dir().join(name);
+    onnx::save(&onnx::export(checkpoint, 64, 64).unwrap(), &path).unwrap();
+    onnx::load(&path).unwrap().graph.unwrap()
+}
+
+fn count(graph: &GraphProto, op_type: &str) -> usize {
+    graph.node.iter().filter(|node| node.op_type == op_type).count()
+}
+
+fn dims(graph: &GraphProto, name: &str) -> Vec<i64> {
+    graph.initializer.iter().find(|tensor| tensor.name == name).unwrap_or_else(|| panic!("No initializer {name}")).dims.clone()
+}
+
+fn output_dims(graph: &GraphProto) -> Vec<Option<i64>> {
+    let output = &graph.output[0];
+    assert_eq!(output.name, "output");
+    let shape = output.r#type.as_ref().unwrap().tensor_type.as_ref().unwrap().shape.as_ref().unwrap();
+    shape.dim.iter().map(|dim| dim.dim_value).collect()
+}
+
+#[test]
+fn unet_export_reimports_with_expected_nodes_and_shapes() {
+    let config = common::config(1, 3, 2, 4);
+    let mut tensors = Vec::new();
+    push_unet(&mut tensors, "", 1, 3, 2, 4, UNetConfig::default());
+    let checkpoint = Checkpoint { config, epoch: 1, tensors };
+    let graph = roundtrip(&checkpoint, "unet_export_test.onnx");
+
+    //Three levels with four convs each, two pool/upsample/crop/concat steps
+    assert_eq!(count(&graph, "Conv"), 12);
+    assert_eq!(count(&graph, "LeakyRelu"), 12);
+    assert_eq!(count(&graph, "MaxPool"), 2);
+    assert_eq!(count(&graph, "Resize"), 2);
+    assert_eq!(count(&graph, "Slice"), 2);
+    assert_eq!(count(&graph, "Concat"), 2);
+    assert_eq!(graph.node.last().unwrap().output, vec!["output".to_string()]);
+
+    //Every conv weight is OIHW with the checkpoint's values
+    for tensor in checkpoint.tensors.iter() {
+        let expected = match tensor.shape[..] {
+            [groups, outputs, kh, kw, inputs] => vec![groups * outputs, inputs, kh, kw],
+            _ => tensor.shape.clone(),
+        };
+        assert_eq!(dims(&graph, &tensor.name), expected.iter().map(|&d| d as i64).collect::<Vec<_>>(), "{}", tensor.name);
+    }
+    let conv3 = graph.initializer.iter().find(|tensor| tensor.name == "conv3.filter").unwrap();
+    //conv3 is [1, 4, 3, 3, 12] in descent, element (o=1, y=2, x=0, i=5) moves to (o=1, i=5, y=2, x=0)
+    assert_eq!(conv3.float_data[((12 + 5) * 3 + 2) * 3], (((3 + 2) * 3) * 12 + 5) as f32);
+    assert_eq!(dims(&graph, "upsample.scales"), vec![4]);
+    assert_eq!(dims(&graph, "inner.crop.starts"), vec![2]);
+
+    //64 -> 60 -> 30 -> 26 -> 13 -> 9 -> 5, upsampled x6 and cropped to 26 -> 22, upsampled x3 and cropped to 60 -> 56
+    assert_eq!(output_dims(&graph), vec![None, Some(3), Some(56), Some(56)]);
+}
+
+#[test]
+fn fcn_export_includes_normalization() {
+    let mut config = common::config(1, 3, 2, 4);
+    config.model.architecture = Architecture::Fcn;
+    config.model.depth = 1;
+    config.model.normalization = Some(Normalization { mean: vec![0.5], std: vec![0.25] });
+    let mut tensors = Vec::new();
+    push_conv(&mut tensors, "convs.0", 1, 4, 3);
+    push_conv(&mut tensors, "convs.1", 4, 4, 3);
+    push_conv(&mut tensors, "convs.2", 4, 3, 3);
+    let checkpoint = Checkpoint { config, epoch: 1, tensors };
+    let graph = roundtrip(&checkpoint, "fcn_export_test.onnx");
+
+    let op_types = graph.node.iter().map(|node| node.op_type.as_str()).collect::<Vec<_>>();
+    assert_eq!(op_types, ["Sub", "Div", "Conv", "LeakyRelu", "Conv", "LeakyRelu", "Conv", "LeakyRelu"]);
+    assert_eq!(dims(&graph, "normalization.mean"), vec![1, 1, 1, 1]);
+    assert_eq!(dims(&graph, "convs.2.filter"), vec![3, 4, 3, 3]);
+    assert_eq!(output_dims(&graph), vec![None, Some(3), Some(58), Some(58)]);
+}
+
+#[test]
+fn residual_unet_export_adds_cropped_projections() {
+    let mut config = common::config(1, 3, 2, 4);
+    config.model.depth = 1;
+    config.model.unet.block = Block::Residual;
+    let checkpoint_unet = config.model.unet;
+    let mut tensors = Vec::new();
+    push_unet(&mut tensors, "", 1, 3, 1, 4, checkpoint_unet);
+    let checkpoint = Checkpoint { config, epoch: 1, tensors };
+    let graph = roundtrip(&checkpoint, "resunet_export_test.onnx");
+
+    //Two levels with two blocks each, every block has a cropped 1x1 shortcut
+    assert_eq!(count(&graph, "Conv"), 12);
+    assert_eq!(count(&graph, "Add"), 4);
+    assert_eq!(count(&graph, "Slice"), 5);
+    assert_eq!(count(&graph, "LeakyRelu"), 8);
+    assert_eq!(dims(&graph, "inner.decoder_shortcut.filter"), vec![8, 8, 1, 1]);
+    assert_eq!(dims(&graph, "decoder_shortcut.filter"), vec![3, 12, 1, 1]);
+
+    //Blocks lose 4 pixels: 64 -> 60 -> 30 -> 26 -> 22, upsampled x3 and cropped to 60 -> 56
+    assert_eq!(output_dims(&graph), vec![None, Some(3), Some(56), Some(56)]);
+}
+
+#[test]
+fn attention_unet_export_gates_every_skip_connection() {
+    let mut config = common::config(1, 3, 2, 4);
+    config.model.unet.attention = true;
+    let mut tensors = Vec::new();
+    push_unet(&mut tensors, "", 1, 3, 2, 4, config.model.unet);
+    let checkpoint = Checkpoint { config, epoch: 1, tensors };
+    let graph = roundtrip(&checkpoint, "attention_unet_export_test.onnx");
+
+    //Three 1x1 convs, an add, a sigmoid and the weighting per gated level
+    assert_eq!(count(&graph, "Conv"), 12 + 2 * 3);
+    assert_eq!(count(&graph, "Add"), 2);
+    assert_eq!(count(&graph, "Sigmoid"), 2);
+    assert_eq!(count(&graph, "Mul"), 2);
+    assert_eq!(dims(&graph, "attention.gate.filter"), vec![4, 8, 1, 1]);
+    assert_eq!(dims(&graph, "inner.attention.psi.filter"), vec![1, 8, 1, 1]);
+    //Gates do not change any shape
+    assert_eq!(output_dims(&graph), vec![None, Some(3), Some(56), Some(56)]);
+}
+
+#[test]
+fn unet_plus_plus_export_builds_every_nested_node() {
+    let mut config = common::config(1, 3, 2, 4);
+    config.model.architecture = Architecture::UnetPlusPlus;
+    config.model.unet.deep_supervision = true;
+    //Same layers as UNetPlusPlus::new, node (i, j) has 4 * 2^i channels
+    let mut tensors = Vec::new();
+    let channels = |i: usize| 4 << i;
+    for (i, j) in [(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (0, 2)] {
+        let inputs = match (i, j) {
+            (0, 0) => 1,
+            (_, 0) => channels(i - 1),
+            _ => j * channels(i) + channels(i + 1),
+        };
+        push_conv(&mut tensors, &format!("node{i}_{j}.conv1"), inputs, channels(i), 3);
+        push_conv(&mut tensors, &format!("node{i}_{j}.conv2"), channels(i), channels(i), 3);
+    }
+    push_conv(&mut tensors, "head1", 4, 3, 1);
+    push_conv(&mut tensors, "head2", 4, 3, 1);
+    let checkpoint = Checkpoint { config, epoch: 1, tensors };
+    let graph = roundtrip(&checkpoint, "unet_plus_plus_export_test.onnx");
+
+    //Six nodes and only the last head, deep supervision heads are left out of the export
+    assert_eq!(count(&graph, "Conv"), 13);
+    assert!(graph.initializer.iter().all(|tensor| !tensor.name.starts_with("head1")));
+    assert_eq!(count(&graph, "MaxPool"), 2);
+    assert_eq!(count(&graph, "Resize"), 3);
+    assert_eq!(count(&graph, "Concat"), 3);
+    //Three upsampled nodes, and node (0, 0) is cropped from 60 to 56 for node (0, 2)
+    assert_eq!(count(&graph, "Slice"), 4);
+    assert_eq!(dims(&graph, "node0_2.conv1.filter"), vec![4, 16, 3, 3]);
+
+    //Node (0, 1) is 56 like the UNet output, node (0, 2) loses another 4 pixels
+    assert_eq!(output_dims(&graph), vec![None, Some(3), Some(52), Some(52)]);
+}
+
+#[test]
+fn unet_export_downsamples_with_the_configured_window() {
+    let mut config = common::config(1, 3, 2, 4);
+    config.model.depth = 1;
+    config.model.unet.pool_size = 3;
+    for downsampling in [Downsampling::Average, Downsampling::StridedConv] {
+        config.model.unet.downsampling = downsampling;
+        let mut tensors = Vec::new();
+        push_unet(&mut tensors, "", 1, 3, 1, 4, config.model.unet);
+        let checkpoint = Checkpoint { config: config.clone(), epoch: 1, tensors };
+        let graph = roundtrip(&checkpoint, "downsampling_export_test.onnx");
+
+        assert_eq!(count(&graph, "MaxPool"), 0);
+        if downsampling == Downsampling::StridedConv {
+            assert_eq!(count(&graph, "Conv"), 9);
+            assert_eq!(dims(&graph, "downsample.filter"), vec![4, 4, 3, 3]);
+        } else {
+            assert_eq!(count(&graph, "Conv"), 8);
+            assert_eq!(count(&graph, "AveragePool"), 1);
+        }
+        //64 -> 60 -> 20 -> 16 -> 12, upsampled x5 to exactly 60 -> 56, the strided conv also ends at (60 - 3) / 3 + 1 = 20
+        assert_eq!(output_dims(&graph), vec![None, Some(3), Some(56), Some(56)]);
+    }
+}
@@ -0,0 +1,35 @@
+use descent::{module::*, prelude::*};
+use descent_unet_example::{checkpoint::Checkpoint, network::Network, reference::{self, FeatureMap}};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+mod common;
+use common::run_descent;
+
+//Plain UNets with freshly reset weights: descent and the CPU reference have to agree on any input size,
+//including ones where the skip connections need uneven crops
+#[test]
+fn reference_matches_descent_for_plain_unets() {
+    let mut rng = StdRng::seed_from_u64(41);
+    for (depth, width, size) in [(0, 4, 13), (1, 4, 37), (2, 2, 60), (2, 3, 67)] {
+        let config = common::config(2, 3, depth, width);
+        let model = &config.model;
+
+        let mut env = Environment::new();
+        let network = Network::new(&mut env, model);
+        let parameters = network.parameters(&mut env, [1, size, size, model.inputs]);
+        for param in parameters.iter() {
+            env.reset_parameter(param, &mut rng);
+        }
+        let names = network.parameter_names();
+        let checkpoint = Checkpoint::from_parameters(&mut env, config.clone(), 0, &names, &parameters);
+
+        let input = FeatureMap::new((0 .. size * size * model.inputs).map(|_| rng.gen_range(-1.0 ..= 1.0)).collect(), size, size, model.inputs);
+        let actual = run_descent(&mut env, &input, |x| network.test(x));
+
+        let expected = reference::forward(&checkpoint, input).unwrap();
+        assert_eq!((actual.height, actual.width, actual.channels), (expected.height, expected.width, expected.channels), "depth {depth} size {size}");
+        for (i, (a, e)) in actual.values.iter().zip(expected.values.iter()).enumerate() {
+            assert!((a - e).abs() <= 1e-4 + 1e-3 * e.abs(), "depth {depth} size {size} differs at {i}: {a} instead of {e}");
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use descent::prelude::*;
+use descent_unet_example::{reference::{self, FeatureMap}, unet::SkipAlignment};
+use proptest::prelude::*;
+
+mod common;
+use common::run_descent;
+
+//Follows the sizes of a square input through UNet::eval and records the alignment of every skip connection
+//as (outer, inner, alignment). None once a level runs out of pixels
+fn unet_size(size: usize, depth: usize, kernelsize: usize, pool_size: usize, skips: &mut Vec<(usize, usize, SkipAlignment)>) -> Option<usize> {
+    let conv = |size: usize| size.checked_sub(kernelsize - 1).filter(|&size| size > 0);
+    let outer = conv(conv(size)?)?;
+    if depth > 0 {
+        let inner = unet_size(outer / pool_size, depth - 1, kernelsize, pool_size, skips)?;
+        skips.push((outer, inner, SkipAlignment::new((outer, outer), (inner, inner))));
+    }
+    conv(conv(outer)?)
+}
+
+//Upsampled by the smallest whole factors that cover the outer size, then cropped to exactly that size, centered
+fn assert_aligns(outer: (usize, usize), inner: (usize, usize), skip: SkipAlignment) {
+    let (h_outer, w_outer) = outer;
+    let (h_inner, w_inner) = inner;
+    assert!(h_inner * skip.factor_y >= h_outer && (skip.factor_y - 1) * h_inner < h_outer, "{skip:?}");
+    assert!(w_inner * skip.factor_x >= w_outer && (skip.factor_x - 1) * w_inner < w_outer, "{skip:?}");
+    assert_eq!(h_inner * skip.factor_y - skip.top - skip.bottom, h_outer, "{skip:?}");
+    assert_eq!(w_inner * skip.factor_x - skip.left - skip.right, w_outer, "{skip:?}");
+    assert!(skip.left <= skip.right && skip.right - skip.left <= 1, "{skip:?}");
+    assert!(skip.top <= skip.bottom && skip.bottom - skip.top <= 1, "{skip:?}");
+}
+
+//Puts zeros back where crop removed pixels
+fn pad(x: &FeatureMap, left: usize, top: usize, right: usize, bottom: usize) -> FeatureMap {
+    let (height, width) = (x.height + top + bottom, x.width + left + right);
+    let mut values = vec![0.0; height * width * x.channels];
+    for y in 0 .. x.height {
+        let src = y * x.width * x.channels;
+        let dst = ((y + top) * width + left) * x.channels;
+        values[dst .. dst + x.width * x.channels].copy_from_slice(&x.values[src .. src + x.width * x.channels]);
+    }
+    FeatureMap::new(values, height, width, x.channels)
+}
+
+proptest! {
+    #[test]
+    fn unet_skip_connections_align(size in 1usize .. 400, depth in 0usize .. 5, kernelsize in (0usize .. 4).prop_map(|k| 2 * k + 1), pool_size in 2usize .. 5) {
+        let mut skips = Vec::new();
+        if unet_size(size, depth, kernelsize, pool_size, &mut skips).is_some() {
+            prop_assert_eq!(skips.len(), depth);
+            for (outer, inner, skip) in skips {
+                assert_aligns((outer, outer), (inner, inner), skip);
+                //The cropped inner output has to concatenate with the skip connection
+                let upsampled = reference::upsample(&FeatureMap::new(vec![0.0; inner * inner], inner, inner, 1), skip.factor_x, skip.factor_y);
+                let cropped = reference::crop(&upsampled, skip.left, skip.top, skip.right, skip.bottom);
+                let skip_connection = FeatureMap::new(vec![0.0; outer * outer * 2], outer, outer, 2);
+                prop_assert_eq!(reference::concat(&skip_connection, &cropped).channels, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn skip_alignment_holds_for_any_smaller_inner_size(
+        (h_outer, h_inner) in (1usize .. 300).prop_flat_map(|outer| (Just(outer), 1 ..= outer)),
+        (w_outer, w_inner) in (1usize .. 300).prop_flat_map(|outer| (Just(outer), 1 ..= outer)),
+    ) {
+        assert_aligns((h_outer, w_outer), (h_inner, w_inner), SkipAlignment::new((h_outer, w_outer), (h_inner, w_inner)));
+    }
+
+    #[test]
+    fn pooling_rounds_down_and_averages_whole_windows(height in 1usize .. 30, width in 1usize .. 30, size in 2usize .. 5, value in -4.0f32 .. 4.0) {
+        let x = FeatureMap::new(vec![value; height * width * 2], height, width, 2);
+        let max = reference::max_pool2d(&x, size);
+        let average = reference::average_pool2d(&x, size);
+        prop_assert_eq!((max.height, max.width), (height / size, width / size));
+        prop_assert_eq!((average.height, average.width, average.channels), (height / size, width / size, 2));
+        for v in average.values.iter().chain(max.values.iter()) {
+            prop_assert!((v - value).abs() <= 1e-5 * value.abs().max(1.0));
+        }
+    }
+
+    #[test]
+    fn crop_then_pad_restores_the_cropped_region(
+        (height, top, bottom) in (1usize .. 24).prop_flat_map(|h| (Just(h), 0 .. h)).prop_flat_map(|(h, top)| (Just(h), Just(top), 0 .. h - top)),
+        (width, left, right) in (1usize .. 24).prop_flat_map(|w| (Just(w), 0 .. w)).prop_flat_map(|(w, left)| (Just(w), Just(left), 0 .. w - left)),
+        channels in 1usize .. 4,
+    ) {
+        let x = FeatureMap::new((0 .. height * width * channels).map(|i| i as f32 + 1.0).collect(), height, width, channels);
+        let cropped = reference::crop(&x, left, top, right, bottom);
+        prop_assert_eq!((cropped.height, cropped.width), (height - top - bottom, width - left - right));
+        let restored = pad(&cropped, left, top, right, bottom);
+        prop_assert_eq!((restored.height, restored.width), (height, width));
+        for y in 0 .. height {
+            for x0 in 0 .. width {
+                let inside = (top .. height - bottom).contains(&y) && (left .. width - right).contains(&x0);
+                let index = (y * width + x0) * channels;
+                let expected = if inside { &x.values[index .. index + channels] } else { &[0.0; 4][.. channels] };
+                prop_assert_eq!(&restored.values[index .. index + channels], expected);
+            }
+        }
+    }
+}
+
+//Every case builds and runs graphs on the GPU, so fewer of them
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn descent_upsample_and_crop_match_the_reference(
+        (height, width, channels) in (1usize .. 12, 1usize .. 12, 1usize .. 4),
+        (factor_x, factor_y) in (1usize .. 4, 1usize .. 4),
+        margins in (0usize .. 4, 0usize .. 4, 0usize .. 4, 0usize .. 4),
+    ) {
+        let x = FeatureMap::new((0 .. height * width * channels).map(|i| i as f32 * 0.5 - 3.0).collect(), height, width, channels);
+        let expected = reference::upsample(&x, factor_x, factor_y);
+        let upsampled = run_descent(&mut Environment::new(), &x, |x| x.upsample(factor_x, factor_y));
+        prop_assert_eq!((upsampled.height, upsampled.width, upsampled.channels), (expected.height, expected.width, expected.channels));
+        prop_assert_eq!(&upsampled.values, &expected.values);
+
+        //Margins are clamped so that at least one pixel is left
+        let (left, top, right, bottom) = margins;
+        let left = left.min(expected.width - 1);
+        let right = right.min(expected.width - 1 - left);
+        let top = top.min(expected.height - 1);
+        let bottom = bottom.min(expected.height - 1 - top);
+        let expected = reference::crop(&expected, left, top, right, bottom);
+        let cropped = run_descent(&mut Environment::new(), &x, |x| x.upsample(factor_x, factor_y).crop(left, top, right, bottom));
+        prop_assert_eq!((cropped.height, cropped.width, cropped.channels), (expected.height, expected.width, expected.channels));
+        prop_assert_eq!(&cropped.values, &expected.values);
+    }
+}
@@ -0,0 +1,81 @@
+use descent_unet_example::tiling::{mirror_index, mirror_pad, run_tiled};
+
+//(length, before, after, source indices) of numpy.pad(..., mode="reflect") along one axis, e.g. [1, 2, 3, 4, 5]
+//padded by (2, 3) is [3, 2, 1, 2, 3, 4, 5, 4, 3, 2]. The others pad by more than the axis is long
+const REFLECT: [(usize, usize, usize, &[usize]); 5] = [
+    (5, 2, 3, &[2, 1, 0, 1, 2, 3, 4, 3, 2, 1]),
+    (3, 5, 4, &[1, 0, 1, 2, 1, 0, 1, 2, 1, 0, 1, 2]),
+    (3, 4, 2, &[0, 1, 2, 1, 0, 1, 2, 1, 0]),
+    (2, 3, 1, &[1, 0, 1, 0, 1, 0]),
+    (1, 2, 3, &[0, 0, 0, 0, 0, 0]),
+];
+
+//Value 10 * y + x in the first channel and its negative in the second
+fn ramp(height: usize, width: usize) -> Vec<f32> {
+    (0 .. height).flat_map(|y| (0 .. width).flat_map(move |x| [(10 * y + x) as f32, -((10 * y + x) as f32)])).collect()
+}
+
+#[test]
+fn mirror_index_matches_numpy_reflect() {
+    for (n, before, after, expected) in REFLECT {
+        let actual = (-(before as isize) .. (n + after) as isize).map(|i| mirror_index(i, n)).collect::<Vec<_>>();
+        assert_eq!(actual, expected, "{n} padded by ({before}, {after})");
+    }
+}
+
+#[test]
+fn mirror_pad_matches_numpy_reflect_on_both_axes() {
+    for (height, top, bottom, rows) in REFLECT {
+        for (width, left, right, cols) in REFLECT {
+            let padded = mirror_pad(&ramp(height, width), height, width, 2, (top, bottom, left, right));
+            let expected = rows.iter().flat_map(|&y| cols.iter().flat_map(move |&x| [(10 * y + x) as f32, -((10 * y + x) as f32)])).collect::<Vec<_>>();
+            assert_eq!(padded, expected, "{height}x{width} padded by ({top}, {bottom}, {left}, {right})");
+        }
+    }
+}
+
+//Stands in for a network that loses a border of 3 pixels: every output tile is the 6x4 window of the
+//12x10 input tile starting shift rows below the center one
+fn cropping_batch(shift: usize) -> impl FnMut(&[f32]) -> Vec<f32> {
+    move |xs: &[f32]| {
+        xs.chunks(12 * 10 * 2)
+            .flat_map(|tile| (0 .. 6).flat_map(move |y| {
+                let start = ((y + 3 + shift) * 10 + 3) * 2;
+                tile[start .. start + 4 * 2].to_vec()
+            }))
+            .collect()
+    }
+}
+
+#[test]
+fn run_tiled_rebuilds_images_of_any_size() {
+    for (height, width) in [(1, 1), (6, 4), (13, 7), (25, 9), (5, 30)] {
+        let image = ramp(height, width);
+        for batch_size in [1, 3] {
+            let output = run_tiled(&image, height, width, (12, 10, 2), (6, 4, 2), batch_size, cropping_batch(0));
+            assert_eq!(output, image, "{height}x{width} with batches of {batch_size}");
+        }
+    }
+}
+
+#[test]
+fn run_tiled_keeps_a_shift_of_the_network() {
+    for (height, width) in [(2, 3), (13, 7), (25, 9)] {
+        let image = ramp(height, width);
+        let output = run_tiled(&image, height, width, (12, 10, 2), (6, 4, 2), 2, cropping_batch(1));
+        //Every pixel shows the one below it, the last row the mirrored one above it
+        let expected = (0 .. height)
+            .flat_map(|y| {
+                let row = mirror_index(y as isize + 1, height);
+                image[row * width * 2 .. (row + 1) * width * 2].to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(output, expected, "{height}x{width}");
+    }
+}
+
+#[test]
+#[should_panic(expected = "Can not tile an empty 5x0 image")]
+fn run_tiled_rejects_empty_images() {
+    run_tiled(&[], 0, 5, (12, 10, 2), (6, 4, 2), 1, cropping_batch(0));
+}